@@ -0,0 +1,3 @@
+-- Store fractional prices.
+
+ALTER TABLE prices ALTER COLUMN price TYPE DOUBLE PRECISION;
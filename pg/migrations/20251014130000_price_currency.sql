@@ -0,0 +1,6 @@
+-- Store prices in several currencies. Existing prices are in USD.
+
+ALTER TABLE prices ADD COLUMN currency TEXT NOT NULL DEFAULT 'usd';
+ALTER TABLE prices ALTER COLUMN currency DROP DEFAULT;
+ALTER TABLE prices DROP CONSTRAINT prices_pkey;
+ALTER TABLE prices ADD PRIMARY KEY (currency, datetime);
@@ -0,0 +1,3 @@
+-- When an unchanged price was last queried. Null if only at datetime.
+
+ALTER TABLE prices ADD COLUMN last_seen TIMESTAMP;
@@ -0,0 +1,3 @@
+-- Queried price info including market data, if storing it is enabled. Only set on USD rows.
+
+ALTER TABLE prices ADD COLUMN raw JSONB;
@@ -0,0 +1,11 @@
+-- Failed price queries, if recording them is enabled.
+
+CREATE TABLE errors (
+    id BIGSERIAL PRIMARY KEY,
+    datetime TIMESTAMP NOT NULL,
+    kind TEXT NOT NULL,
+    status SMALLINT,
+    message TEXT NOT NULL
+);
+
+CREATE INDEX errors_datetime ON errors (datetime);
@@ -0,0 +1,6 @@
+-- Successful price queries per minute, if recording failures is enabled.
+
+CREATE TABLE query_successes (
+    minute TIMESTAMP PRIMARY KEY,
+    count INTEGER NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Index prices by time alone. The primary key leads with currency, but queries of all currencies
+-- filter by time only.
+
+CREATE INDEX prices_datetime_idx ON prices (datetime);
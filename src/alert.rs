@@ -0,0 +1,84 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::Url;
+use tokio_util::sync::CancellationToken;
+
+use crate::source::LastSuccess;
+
+/// How often to check whether price queries are failing for too long.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Callbacks invoked when price queries fail for too long and when they recover.
+pub struct DegradedAlert {
+    /// How long queries must fail to become degraded.
+    pub threshold: Duration,
+
+    /// Called once on becoming degraded, with the time since the last successful query.
+    pub on_degraded: Box<dyn Fn(Duration) + Send + Sync>,
+
+    /// Called once on recovery.
+    pub on_recovered: Box<dyn Fn() + Send + Sync>,
+}
+
+impl DegradedAlert {
+    /// Alert logging state changes and posting them to a Slack compatible `webhook`, if any.
+    pub fn logging(threshold: Duration, webhook: Option<Url>) -> Self {
+        let client = reqwest::Client::new();
+        let notify = move |text: String| {
+            if let Some(webhook) = webhook.clone() {
+                let request = client
+                    .post(webhook)
+                    .json(&serde_json::json!({ "text": text }))
+                    .send();
+                tokio::spawn(async move {
+                    if let Err(e) = request.await.and_then(|r| r.error_for_status()) {
+                        tracing::warn!("Failed to post alert: {e}.");
+                    }
+                });
+            }
+        };
+        let notify = Arc::new(notify);
+        let notify_recovered = notify.clone();
+
+        Self {
+            threshold,
+            on_degraded: Box::new(move |failing_for| {
+                let text = format!("BTC price queries failing for {}s.", failing_for.as_secs());
+                tracing::error!("{text}");
+                notify(text);
+            }),
+            on_recovered: Box::new(move || {
+                let text = "BTC price queries recovered.".to_string();
+                tracing::info!("{text}");
+                notify_recovered(text);
+            }),
+        }
+    }
+}
+
+/// Invoke `alert` callbacks when queries tracked by `last_success` change between failing for
+/// longer than the threshold and succeeding again, until `shutdown` is cancelled.
+pub async fn watch_degraded(
+    alert: DegradedAlert,
+    last_success: Arc<LastSuccess>,
+    shutdown: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut degraded = false;
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        let failing_for = last_success.failing_for();
+        if !degraded && failing_for > alert.threshold {
+            degraded = true;
+            (alert.on_degraded)(failing_for);
+        } else if degraded && failing_for <= alert.threshold {
+            degraded = false;
+            (alert.on_recovered)();
+        }
+    }
+}
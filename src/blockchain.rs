@@ -0,0 +1,144 @@
+use crate::clock::{Clock, SystemClock};
+use crate::error::QueryError;
+use crate::http::{self, ApiClient, HttpConfig};
+use crate::source::PriceSource;
+use crate::{Price, PriceInfo};
+use std::sync::Arc;
+
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+
+/// Blockchain.com exchange rates endpoint.
+const TICKER_URL: &str = "https://blockchain.info/ticker";
+
+/// Blockchain.com exchange rates API client.
+pub struct Blockchain {
+    api: ApiClient,
+    url: String,
+
+    /// Clock stamping queried prices.
+    clock: Box<dyn Clock>,
+}
+
+/// Exchange rates response.
+#[derive(Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct Ticker {
+    usd: Rate,
+    eur: Option<Rate>,
+    gbp: Option<Rate>,
+}
+
+/// Exchange rate of a single currency.
+#[derive(Deserialize)]
+struct Rate {
+    /// Last trade price.
+    #[serde(deserialize_with = "crate::de::number_or_string")]
+    last: f64,
+}
+
+impl Blockchain {
+    /// Client configured by `http`.
+    pub fn new(http: &HttpConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            api: ApiClient::new(http)?,
+            url: TICKER_URL.to_string(),
+            clock: Box::new(SystemClock),
+        })
+    }
+
+    /// Query `url` instead of the Blockchain.com endpoint.
+    #[cfg(test)]
+    pub fn with_url(self, url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            ..self
+        }
+    }
+
+    /// Stamp queried prices with time read from `clock`.
+    #[cfg(test)]
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+            ..self
+        }
+    }
+
+    /// Query latest BTC price. The API doesn't report update time, so the time read from the
+    /// clock is used.
+    #[tracing::instrument(skip(self), fields(asset = "bitcoin", outcome))]
+    pub async fn query_price(&self) -> Result<Price, QueryError> {
+        let result = self.query_ticker().await;
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        tracing::Span::current().record("outcome", outcome);
+        let (ticker, response) = result?;
+
+        Ok(Price {
+            bitcoin: PriceInfo {
+                usd: ticker.usd.last,
+                eur: ticker.eur.map(|r| r.last),
+                gbp: ticker.gbp.map(|r| r.last),
+                usd_market_cap: None,
+                usd_24h_vol: None,
+                usd_24h_change: None,
+                last_updated_at: self.clock.now(),
+            },
+            source: Some(self.name()),
+            change: None,
+            confidence: None,
+            raw: Some(Arc::new(response)),
+        })
+    }
+
+    /// Query exchange rates, returning them with the response they were parsed from.
+    async fn query_ticker(&self) -> Result<(Ticker, serde_json::Value), QueryError> {
+        let body = self.api.get_body(&self.url, &[]).await?;
+        let snippet_len = self.api.snippet_len();
+        let response: serde_json::Value = http::parse_json(&body, snippet_len)?;
+        let ticker = Ticker::deserialize(&response).map_err(|error| QueryError::Parse {
+            error,
+            snippet: http::snippet(&body, snippet_len),
+        })?;
+        Ok((ticker, response))
+    }
+}
+
+impl PriceSource for Blockchain {
+    fn name(&self) -> &'static str {
+        "blockchain.com"
+    }
+
+    fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+        Box::pin(self.query_price())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::routing::get;
+
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn price_is_stamped_by_clock() {
+        let body = r#"{"USD":{"last":63214.57},"EUR":{"last":"58000.1"}}"#;
+        let url =
+            testing::serve(Router::new().route("/ticker", get(move || async move { body }))).await;
+        let client = Blockchain::new(&testing::http_config())
+            .unwrap()
+            .with_url(url.join("ticker").unwrap())
+            .with_clock(FixedClock(1_700_000_000));
+
+        let price = client.query_price().await.unwrap();
+
+        assert_eq!(price.bitcoin.usd, 63214.57);
+        assert_eq!(price.bitcoin.eur, Some(58000.1));
+        assert_eq!(price.bitcoin.last_updated_at, 1_700_000_000);
+        assert_eq!(price.source, Some("blockchain.com"));
+        assert_eq!(price.raw.unwrap()["EUR"]["last"], "58000.1");
+    }
+}
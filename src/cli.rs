@@ -0,0 +1,126 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use reqwest::Url;
+use sqlx::types::chrono::NaiveDate;
+
+use crate::Currency;
+
+/// BTC price tracker.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// CoinGecko compatible simple price endpoint. Overrides `PRICE_API_URL`.
+    #[arg(long, global = true)]
+    pub api_url: Option<Url>,
+
+    /// Comma separated currencies to query besides USD. Overrides `PRICE_CURRENCIES`.
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub currency: Vec<Currency>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Query the price once and print it as JSON.
+    Once,
+
+    /// Query the price periodically and print each one as a JSON line.
+    Watch(WatchArgs),
+
+    /// Query the price once and check that it parses to a plausible value.
+    Verify,
+
+    /// Query the price once, store it and print the stored price as JSON.
+    Store(StoreArgs),
+
+    /// Periodically query, store and serve prices. Runs if no command is given.
+    Daemon(DaemonArgs),
+
+    /// Print stored prices in a time range as CSV.
+    Export(ExportArgs),
+
+    /// Print prices stored since the previous sync as JSON lines.
+    Sync(SyncArgs),
+
+    /// Store prices read from CSV on stdin.
+    Import(ImportArgs),
+
+    /// Store daily historical prices from price sources that keep history.
+    Backfill(BackfillArgs),
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    /// Seconds between price queries. Overrides `QUERY_PERIOD_SECS`.
+    #[arg(long)]
+    pub interval: Option<u64>,
+}
+
+#[derive(Args, Default)]
+pub struct DaemonArgs {
+    /// Seconds between price queries. Overrides `QUERY_PERIOD_SECS`.
+    #[arg(long)]
+    pub interval: Option<u64>,
+
+    /// Postgres connection string. Overrides `POSTGRES_CONN_STR`.
+    #[arg(long)]
+    pub db_url: Option<String>,
+}
+
+#[derive(Args)]
+pub struct StoreArgs {
+    /// Postgres connection string. Overrides `POSTGRES_CONN_STR`.
+    #[arg(long)]
+    pub db_url: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Unix timestamp of the first price to export.
+    #[arg(long, default_value_t = 0)]
+    pub from: u64,
+
+    /// Unix timestamp of the last price to export. Defaults to now.
+    #[arg(long)]
+    pub to: Option<u64>,
+
+    /// Postgres connection string. Overrides `POSTGRES_CONN_STR`.
+    #[arg(long)]
+    pub db_url: Option<String>,
+}
+
+#[derive(Args)]
+pub struct SyncArgs {
+    /// File keeping the timestamp of the last printed price. Created on the first sync.
+    #[arg(long)]
+    pub cursor: PathBuf,
+
+    /// Postgres connection string. Overrides `POSTGRES_CONN_STR`.
+    #[arg(long)]
+    pub db_url: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// Postgres connection string. Overrides `POSTGRES_CONN_STR`.
+    #[arg(long)]
+    pub db_url: Option<String>,
+}
+
+#[derive(Args)]
+pub struct BackfillArgs {
+    /// First date to store the price of, as `YYYY-MM-DD`.
+    #[arg(long)]
+    pub from: NaiveDate,
+
+    /// Last date to store the price of, as `YYYY-MM-DD`. Defaults to today.
+    #[arg(long)]
+    pub to: Option<NaiveDate>,
+
+    /// Postgres connection string. Overrides `POSTGRES_CONN_STR`.
+    #[arg(long)]
+    pub db_url: Option<String>,
+}
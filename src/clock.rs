@@ -0,0 +1,27 @@
+use sqlx::types::chrono::Utc;
+
+/// Source of the current time, replaceable to test timestamps.
+pub trait Clock: Send + Sync {
+    /// Current Unix timestamp in seconds.
+    fn now(&self) -> u64;
+}
+
+/// Clock reading the system time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        Utc::now().timestamp() as u64
+    }
+}
+
+/// Clock always reading the given Unix timestamp.
+#[cfg(test)]
+pub struct FixedClock(pub u64);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,737 @@
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use reqwest::Url;
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::Deserialize;
+use sqlx::types::chrono::{NaiveDate, NaiveTime};
+use tracing::Instrument;
+
+use crate::error::QueryError;
+use crate::http::{self, ApiClient, Fetched, HttpConfig, Validators};
+use crate::rate_limit::RetryBudget;
+use crate::source::PriceSource;
+use crate::{Currency, Price, PriceInfo};
+
+/// Default CoinGecko simple price endpoint.
+pub const DEFAULT_URL: &str = "https://api.coingecko.com/api/v3/simple/price";
+
+/// Default range of accepted USD prices.
+pub const DEFAULT_PRICE_BOUNDS: RangeInclusive<f64> = 1.0..=100_000_000.0;
+
+/// Timeout of the API availability check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How to retry failed price requests.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+
+    /// Delay before the first retry. Doubles with every next retry.
+    pub base_delay: Duration,
+
+    /// Maximum honored delay requested by the API with `Retry-After`.
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(250),
+            max_retry_after: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (starting from 0) after `error`.
+    ///
+    /// Honors the delay requested by a rate limited API, up to `max_retry_after`.
+    fn delay_after(&self, retry: u32, error: &QueryError) -> Duration {
+        match error {
+            QueryError::RateLimited {
+                retry_after: Some(delay),
+                ..
+            } => (*delay).min(self.max_retry_after),
+            _ => self.delay(retry),
+        }
+    }
+
+    /// Delay before retry number `retry` (starting from 0), with up to 50% of jitter.
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = self.base_delay.saturating_mul(2u32.saturating_pow(retry));
+        let jitter = rand::random_range(0..=delay.as_millis() as u64 / 2);
+        delay + Duration::from_millis(jitter)
+    }
+}
+
+/// Price with details of how it was queried.
+#[derive(Debug, Clone)]
+pub struct QueryResult {
+    pub price: Price,
+
+    /// Name of the source that provided the price.
+    pub source: &'static str,
+
+    /// Number of attempts made, including the successful one.
+    pub attempts: u32,
+
+    /// Time spent querying, including retries.
+    pub elapsed: Duration,
+}
+
+/// CoinGecko price API client.
+#[derive(Clone)]
+pub struct CoinGecko {
+    api: Arc<ApiClient>,
+    url: Url,
+    retry: RetryPolicy,
+    retry_budget: Option<Arc<RetryBudget>>,
+    vs_currencies: String,
+    bounds: RangeInclusive<f64>,
+
+    /// Latest queried price with validators of its response, reused while it is not modified.
+    last: Arc<Mutex<Option<(Validators, Price)>>>,
+}
+
+impl CoinGecko {
+    /// Start configuring a client.
+    pub fn builder() -> CoinGeckoBuilder {
+        CoinGeckoBuilder::default()
+    }
+
+    /// Check that the API is reachable using its `ping` endpoint next to the price endpoint.
+    pub async fn check(&self) -> Result<(), QueryError> {
+        self.api
+            .check(self.endpoint(&["ping"]), CHECK_TIMEOUT)
+            .await
+    }
+
+    /// Query latest BTC price, retrying failures according to the retry policy.
+    pub async fn query_price(&self) -> Result<Price, QueryError> {
+        let result = self.query_price_detailed().await?;
+        tracing::debug!(
+            "Queried price from {} in {} attempts, {:?}.",
+            result.source,
+            result.attempts,
+            result.elapsed
+        );
+        Ok(result.price)
+    }
+
+    /// Query latest BTC price like [`CoinGecko::query_price`], reporting how it was obtained.
+    pub async fn query_price_detailed(&self) -> Result<QueryResult, QueryError> {
+        let started = Instant::now();
+        let (result, attempts) = self.query_price_attempts().await;
+        Ok(QueryResult {
+            price: result?,
+            source: self.name(),
+            attempts,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    /// Query latest BTC price, retrying failures according to the retry policy.
+    /// Returns the result of the last attempt and the number of attempts made.
+    #[tracing::instrument(
+        skip(self),
+        fields(asset = "bitcoin", currencies = %self.vs_currencies, outcome)
+    )]
+    async fn query_price_attempts(&self) -> (Result<Price, QueryError>, u32) {
+        let span = tracing::Span::current();
+        let mut attempt = 1;
+        loop {
+            let query = self
+                .query_price_once()
+                .instrument(tracing::debug_span!("attempt", attempt));
+            match query.await {
+                Ok(price) => {
+                    span.record("outcome", "success");
+                    return (Ok(price), attempt);
+                }
+                Err(e)
+                    if attempt < self.retry.max_attempts
+                        && self.retry_budget.as_ref().is_some_and(|b| !b.try_take()) =>
+                {
+                    tracing::debug!("Price query attempt {attempt} failed: {e}. No retries left.");
+                    span.record("outcome", "failure");
+                    return (Err(QueryError::RetryBudgetExhausted(Box::new(e))), attempt);
+                }
+                Err(e) if attempt < self.retry.max_attempts => {
+                    let delay = self.retry.delay_after(attempt - 1, &e);
+                    tracing::debug!(
+                        "Price query attempt {attempt} failed: {e}. Retry in {delay:?}."
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::record_retry();
+                }
+                Err(e) => {
+                    tracing::debug!("Price query failed after {attempt} attempts.");
+                    span.record("outcome", "failure");
+                    return (Err(e), attempt);
+                }
+            }
+        }
+    }
+
+    /// Query BTC price at the start of `date` (UTC) using the coin history endpoint
+    /// next to the price endpoint. Prices outside of the price bounds are failed queries.
+    pub async fn query_price_at(&self, date: NaiveDate) -> Result<Price, QueryError> {
+        let history = self.endpoint(&["coins", "bitcoin", "history"]);
+        let date_param = date.format("%d-%m-%Y").to_string();
+        let params = [("date", date_param.as_str()), ("localization", "false")];
+        let snapshot: HistorySnapshot = self.api.get_json(history, &params).await?;
+        let current = snapshot.market_data.current_price;
+        self.check_bounds(current.usd)?;
+
+        Ok(Price {
+            bitcoin: PriceInfo {
+                usd: current.usd,
+                eur: current.eur,
+                gbp: current.gbp,
+                usd_market_cap: None,
+                usd_24h_vol: None,
+                usd_24h_change: None,
+                last_updated_at: date.and_time(NaiveTime::MIN).and_utc().timestamp() as u64,
+            },
+            source: Some(self.name()),
+            change: None,
+            confidence: None,
+            raw: None,
+        })
+    }
+
+    /// Query latest BTC price once.
+    ///
+    /// The request is conditional on the response having changed since the previous price was
+    /// queried, reusing the previous price if not.
+    async fn query_price_once(&self) -> Result<Price, QueryError> {
+        let params = [
+            ("vs_currencies", self.vs_currencies.as_str()),
+            ("ids", "bitcoin"),
+            ("include_last_updated_at", "true"),
+            ("include_market_cap", "true"),
+            ("include_24hr_vol", "true"),
+            ("include_24hr_change", "true"),
+        ];
+        let last = self
+            .last
+            .lock()
+            .expect("last price is not poisoned")
+            .clone();
+        let validators = last.as_ref().map(|(v, _)| v.clone()).unwrap_or_default();
+        let fetched = self
+            .api
+            .get_body_if_modified(self.url.clone(), &params, &validators)
+            .await?;
+        let (body, validators) = match (fetched, last) {
+            (Fetched::Modified { body, validators }, _) => (body, validators),
+            (Fetched::NotModified, Some((_, price))) => {
+                tracing::debug!("Price response not modified, reusing previous price.");
+                return Ok(price);
+            }
+            (Fetched::NotModified, None) => unreachable!("validators come with a previous price"),
+        };
+
+        let mut price = parse_price(&body, self.api.snippet_len())?;
+        self.check_bounds(price.bitcoin.usd)?;
+        price.source = Some(self.name());
+        let cached = (!validators.is_empty()).then_some((validators, price.clone()));
+        *self.last.lock().expect("last price is not poisoned") = cached;
+        Ok(price)
+    }
+
+    /// Fail with [`QueryError::OutOfBounds`] if `usd` is outside of the price bounds.
+    fn check_bounds(&self, usd: f64) -> Result<(), QueryError> {
+        if !self.bounds.contains(&usd) {
+            return Err(QueryError::OutOfBounds {
+                usd,
+                bounds: self.bounds.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// URL of the API endpoint at `path` below the API root, two levels above the price
+    /// endpoint, keeping query parameters of the price endpoint like API keys.
+    fn endpoint(&self, path: &[&str]) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("price URL has a path")
+            .pop()
+            .pop()
+            .extend(path);
+        url
+    }
+}
+
+/// Parse a simple price response `body`, attaching up to `snippet_len` bytes of it to errors.
+///
+/// Fails with [`QueryError::NoPrice`] if the response is valid JSON without a BTC price, e.g. an
+/// API error message, and with [`QueryError::Parse`] if it is not a valid price response.
+pub fn parse_price(body: &str, snippet_len: usize) -> Result<Price, QueryError> {
+    let response: serde_json::Value = http::parse_json(body, snippet_len)?;
+    if response.get("bitcoin").is_none() {
+        return Err(QueryError::NoPrice {
+            snippet: http::snippet(body, snippet_len),
+        });
+    }
+    let mut price = Price::deserialize(&response).map_err(|error| QueryError::Parse {
+        error,
+        snippet: http::snippet(body, snippet_len),
+    })?;
+    price.raw = Some(Arc::new(response));
+    Ok(price)
+}
+
+impl PriceSource for CoinGecko {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+        Box::pin(self.query_price())
+    }
+
+    fn fetch_retried(&self) -> BoxFuture<'_, (Result<Price, QueryError>, u32)> {
+        Box::pin(async {
+            let (result, attempts) = self.query_price_attempts().await;
+            (result, attempts - 1)
+        })
+    }
+
+    fn fetch_at(&self, date: NaiveDate) -> BoxFuture<'_, Result<Price, QueryError>> {
+        Box::pin(self.query_price_at(date))
+    }
+}
+
+/// Coin history response.
+#[derive(Deserialize)]
+struct HistorySnapshot {
+    market_data: MarketData,
+}
+
+/// Market data of a coin history snapshot.
+#[derive(Deserialize)]
+struct MarketData {
+    current_price: CurrentPrice,
+}
+
+/// Prices of a coin history snapshot.
+#[derive(Deserialize)]
+struct CurrentPrice {
+    usd: f64,
+    eur: Option<f64>,
+    gbp: Option<f64>,
+}
+
+/// Configuration of a [`CoinGecko`] client.
+pub struct CoinGeckoBuilder {
+    url: Url,
+    http: HttpConfig,
+    retry: RetryPolicy,
+    retries_per_minute: Option<u32>,
+    currencies: Vec<Currency>,
+    bounds: RangeInclusive<f64>,
+}
+
+impl Default for CoinGeckoBuilder {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_URL.parse().expect("default URL is valid"),
+            http: HttpConfig::default(),
+            retry: RetryPolicy::default(),
+            retries_per_minute: None,
+            currencies: vec![],
+            bounds: DEFAULT_PRICE_BOUNDS,
+        }
+    }
+}
+
+impl CoinGeckoBuilder {
+    /// Simple price endpoint to query.
+    pub fn url(mut self, url: Url) -> Self {
+        self.url = url;
+        self
+    }
+
+    /// HTTP client settings: timeout, proxy, rate limit.
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Authenticate requests with a header, e.g. `x-cg-demo-api-key`. Its value is redacted in logs.
+    pub fn auth_header(mut self, name: HeaderName, mut value: HeaderValue) -> Self {
+        value.set_sensitive(true);
+        self.http.headers.insert(name, value);
+        self
+    }
+
+    /// How to retry failed requests.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Limit retries of all queries to `per_minute`. Once exhausted, failed queries are not
+    /// retried until the budget refills.
+    pub fn retry_budget(mut self, per_minute: u32) -> Self {
+        self.retries_per_minute = Some(per_minute);
+        self
+    }
+
+    /// Currencies to query in addition to USD, which is always queried.
+    pub fn currencies(mut self, currencies: Vec<Currency>) -> Self {
+        self.currencies = currencies;
+        self
+    }
+
+    /// Range of accepted USD prices. Prices outside of it are failed queries and are retried.
+    pub fn price_bounds(mut self, bounds: RangeInclusive<f64>) -> Self {
+        self.bounds = bounds;
+        self
+    }
+
+    /// Build the client.
+    pub fn build(self) -> anyhow::Result<CoinGecko> {
+        let mut vs_currencies = vec![Currency::Usd.code()];
+        for currency in self.currencies {
+            if !vs_currencies.contains(&currency.code()) {
+                vs_currencies.push(currency.code());
+            }
+        }
+
+        Ok(CoinGecko {
+            api: Arc::new(ApiClient::new(&self.http)?),
+            url: self.url,
+            retry: self.retry,
+            retry_budget: self
+                .retries_per_minute
+                .map(|per_minute| Arc::new(RetryBudget::new(per_minute))),
+            vs_currencies: vs_currencies.join(","),
+            bounds: self.bounds,
+            last: Arc::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::Router;
+    use axum::extract::RawQuery;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::{Html, IntoResponse};
+    use axum::routing::get;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use super::*;
+    use crate::blockchain::Blockchain;
+    use crate::source::CompositePriceSource;
+    use crate::testing::{self, PRICE_BODY};
+
+    /// Query parameter authenticating requests to the mock API.
+    const API_KEY: &str = "x_cg_demo_api_key=secret";
+
+    /// Client of a mock simple price endpoint served by `router`, trying each query once.
+    /// Requests are authenticated with [`API_KEY`] in the configured URL.
+    async fn mock_client(router: Router) -> CoinGecko {
+        mock_client_with(router, 1).await
+    }
+
+    /// Client of a mock simple price endpoint served by `router`, trying each query up to
+    /// `max_attempts` times without delays.
+    async fn mock_client_with(router: Router, max_attempts: u32) -> CoinGecko {
+        let url = testing::serve(router).await;
+        let url = url.join(&format!("api/v3/simple/price?{API_KEY}")).unwrap();
+        CoinGecko::builder()
+            .url(url)
+            .http(testing::http_config())
+            .retry(RetryPolicy {
+                max_attempts,
+                base_delay: Duration::ZERO,
+                ..RetryPolicy::default()
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_price_keeps_fractional_usd() {
+        let price = parse_price(PRICE_BODY, 256).unwrap();
+        assert_eq!(price.bitcoin.usd, 63214.57);
+        assert_eq!(price.bitcoin.usd_cents(), 6321457);
+        assert!(price.to_json().contains("\"usd\":63214.57"));
+    }
+
+    #[test]
+    fn parse_price_reads_market_data() {
+        let body = r#"{"bitcoin":{"usd":63214.57,"eur":"58000.1","usd_24h_change":-1.5,"last_updated_at":1700000000}}"#;
+        let price = parse_price(body, 256).unwrap();
+        assert_eq!(price.bitcoin.eur, Some(58000.1));
+        assert_eq!(price.bitcoin.usd_24h_change, Some(-1.5));
+        assert_eq!(price.bitcoin.gbp, None);
+        assert_eq!(price.bitcoin.last_updated_at, 1700000000);
+        let raw = price.raw.unwrap();
+        assert_eq!(
+            *raw,
+            serde_json::from_str::<serde_json::Value>(body).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_price_without_usd_fails() {
+        let body = r#"{"bitcoin":{"last_updated_at":1700000000}}"#;
+        let error = parse_price(body, 256).unwrap_err();
+        assert!(matches!(error, QueryError::Parse { .. }), "{error}");
+    }
+
+    #[test]
+    fn parse_price_of_malformed_body_fails() {
+        let body = r#"{"bitcoin":{"usd":63214.57"#;
+        let error = parse_price(body, 8).unwrap_err();
+        assert!(
+            matches!(&error, QueryError::Parse { snippet, .. } if snippet == r#"{"bitcoi"#),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn query_price_from_mock_api() {
+        let router = Router::new().route("/api/v3/simple/price", get(|| async { PRICE_BODY }));
+        let price = mock_client(router).await.query_price().await.unwrap();
+        assert_eq!(price.bitcoin.usd, 63214.57);
+        assert_eq!(price.bitcoin.last_updated_at, 1700000000);
+        assert_eq!(price.source, Some("coingecko"));
+    }
+
+    #[tokio::test]
+    async fn query_price_of_gzip_response() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(PRICE_BODY.as_bytes()).unwrap();
+        let body = encoder.finish().unwrap();
+        let router = Router::new().route(
+            "/api/v3/simple/price",
+            get(move || async move { ([("content-encoding", "gzip")], body) }),
+        );
+        let price = mock_client(router).await.query_price().await.unwrap();
+        assert_eq!(price.bitcoin.usd, 63214.57);
+    }
+
+    #[tokio::test]
+    async fn blocked_query_fails_with_status() {
+        let router = Router::new().route(
+            "/api/v3/simple/price",
+            get(|| async {
+                let page = "<html><title>Attention Required! | Cloudflare</title></html>";
+                (StatusCode::FORBIDDEN, Html(page))
+            }),
+        );
+        let error = mock_client(router).await.query_price().await.unwrap_err();
+        assert!(
+            matches!(error, QueryError::NonSuccessStatus { status, .. } if status == 403),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn query_without_price_fails() {
+        let router = Router::new().route(
+            "/api/v3/simple/price",
+            get(|| async { r#"{"error":"coin not found"}"# }),
+        );
+        let error = mock_client(router).await.query_price().await.unwrap_err();
+        assert!(matches!(error, QueryError::NoPrice { .. }), "{error}");
+    }
+
+    #[tokio::test]
+    async fn slow_query_times_out() {
+        let timeout = Duration::from_millis(200);
+        let router = Router::new().route(
+            "/api/v3/simple/price",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                PRICE_BODY
+            }),
+        );
+        let url = testing::serve(router).await;
+        let client = CoinGecko::builder()
+            .url(url.join(&format!("api/v3/simple/price?{API_KEY}")).unwrap())
+            .http(HttpConfig {
+                timeout,
+                ..testing::http_config()
+            })
+            .retry(RetryPolicy {
+                max_attempts: 1,
+                ..RetryPolicy::default()
+            })
+            .build()
+            .unwrap();
+
+        let started = Instant::now();
+        let error = client.query_price().await.unwrap_err();
+
+        assert!(
+            matches!(error, QueryError::Timeout(t) if t == timeout),
+            "{error}"
+        );
+        assert!(started.elapsed() < timeout * 5, "{:?}", started.elapsed());
+    }
+
+    /// Status of a mock endpoint response, rejecting requests without [`API_KEY`].
+    fn authenticated(query: Option<String>) -> StatusCode {
+        let authenticated = query.is_some_and(|q| q.split('&').any(|param| param == API_KEY));
+        if authenticated {
+            StatusCode::OK
+        } else {
+            StatusCode::UNAUTHORIZED
+        }
+    }
+
+    #[tokio::test]
+    async fn check_keeps_api_key() {
+        let router = Router::new().route(
+            "/api/v3/ping",
+            get(|RawQuery(query): RawQuery| async move {
+                (
+                    authenticated(query),
+                    r#"{"gecko_says":"(V3) To the Moon!"}"#,
+                )
+            }),
+        );
+        mock_client(router).await.check().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn history_query_keeps_api_key() {
+        let router = Router::new().route(
+            "/api/v3/coins/bitcoin/history",
+            get(|RawQuery(query): RawQuery| async move {
+                let dated = query
+                    .as_deref()
+                    .is_some_and(|q| q.contains("date=01-01-2024"));
+                let body = r#"{"market_data":{"current_price":{"usd":42280.23,"eur":38273.1}}}"#;
+                (
+                    if dated {
+                        authenticated(query)
+                    } else {
+                        StatusCode::BAD_REQUEST
+                    },
+                    body,
+                )
+            }),
+        );
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let price = mock_client(router)
+            .await
+            .query_price_at(date)
+            .await
+            .unwrap();
+        assert_eq!(price.bitcoin.usd, 42280.23);
+        assert_eq!(price.bitcoin.eur, Some(38273.1));
+        assert_eq!(price.bitcoin.last_updated_at, 1704067200);
+    }
+
+    #[tokio::test]
+    async fn history_query_checks_bounds() {
+        let router = Router::new().route(
+            "/api/v3/coins/bitcoin/history",
+            get(|| async { r#"{"market_data":{"current_price":{"usd":0.0}}}"# }),
+        );
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let error = mock_client(router)
+            .await
+            .query_price_at(date)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, QueryError::OutOfBounds { .. }), "{error}");
+    }
+
+    #[tokio::test]
+    async fn rate_limited_query_is_retried() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let router = Router::new().route(
+            "/api/v3/simple/price",
+            get(move || async move {
+                if counter.fetch_add(1, Ordering::Relaxed) == 0 {
+                    (StatusCode::TOO_MANY_REQUESTS, [("retry-after", "0")], "").into_response()
+                } else {
+                    PRICE_BODY.into_response()
+                }
+            }),
+        );
+
+        let result = mock_client_with(router, 3)
+            .await
+            .query_price_detailed()
+            .await
+            .unwrap();
+
+        assert_eq!(result.price.bitcoin.usd, 63214.57);
+        assert_eq!(result.attempts, 2);
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn unmodified_response_reuses_price() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let router = Router::new().route(
+            "/api/v3/simple/price",
+            get(move |headers: HeaderMap| async move {
+                counter.fetch_add(1, Ordering::Relaxed);
+                if headers
+                    .get("if-none-match")
+                    .is_some_and(|tag| tag == "\"v1\"")
+                {
+                    (StatusCode::NOT_MODIFIED, [("etag", "\"v1\"")], "")
+                } else {
+                    (StatusCode::OK, [("etag", "\"v1\"")], PRICE_BODY)
+                }
+            }),
+        );
+        let client = mock_client(router).await;
+
+        let first = client.query_price().await.unwrap();
+        let second = client.query_price().await.unwrap();
+
+        assert_eq!(second.bitcoin.usd, first.bitcoin.usd);
+        assert_eq!(
+            second.bitcoin.last_updated_at,
+            first.bitcoin.last_updated_at
+        );
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_query_falls_back_to_blockchain() {
+        let router = Router::new()
+            .route(
+                "/api/v3/simple/price",
+                get(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "{}") }),
+            )
+            .route("/ticker", get(|| async { r#"{"USD":{"last":63100.5}}"# }));
+        let url = testing::serve(router.clone()).await;
+        let blockchain = Blockchain::new(&testing::http_config())
+            .unwrap()
+            .with_url(url.join("ticker").unwrap());
+        let source = CompositePriceSource::new(Box::new(mock_client(router).await))
+            .with_fallback(Box::new(blockchain));
+
+        let price = source.fetch().await.unwrap();
+
+        assert_eq!(price.bitcoin.usd, 63100.5);
+        assert_eq!(price.source, Some("blockchain.com"));
+    }
+}
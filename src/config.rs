@@ -0,0 +1,526 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{Url, tls};
+
+use crate::Currency;
+use crate::coingecko::{self, RetryPolicy};
+use crate::http::HttpConfig;
+
+/// How often to query prices by default.
+const DEFAULT_QUERY_PERIOD: Duration = Duration::from_secs(5);
+
+/// How many decimal places stored prices are rounded to by default.
+const DEFAULT_STORE_DECIMALS: u32 = 2;
+
+/// How many prices failing to be stored are kept by default.
+const DEFAULT_WRITE_QUEUE_CAPACITY: usize = 1000;
+
+/// How long without a successful price query the service is healthy by default.
+const DEFAULT_HEALTH_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// How long to wait for the price update routine to stop on shutdown by default.
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header carrying the CoinGecko API key by default.
+const DEFAULT_API_KEY_HEADER: &str = "x-cg-demo-api-key";
+
+/// How to combine price sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    /// Use the first source that succeeds.
+    Fallback,
+
+    /// Query all sources and use the median price.
+    Median,
+
+    /// Use the CoinGecko price only if blockchain.com agrees with it.
+    Verified,
+}
+
+impl FromStr for Aggregation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fallback" => Ok(Self::Fallback),
+            "median" => Ok(Self::Median),
+            "verified" => Ok(Self::Verified),
+            _ => anyhow::bail!("unknown price aggregation: {s}"),
+        }
+    }
+}
+
+/// Where to deliver queried prices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkKind {
+    /// Print JSON lines to stdout.
+    Stdout,
+
+    /// Store every price in the database.
+    Db,
+
+    /// Discard prices.
+    None,
+}
+
+impl FromStr for SinkKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdout" => Ok(Self::Stdout),
+            "db" => Ok(Self::Db),
+            "none" => Ok(Self::None),
+            _ => anyhow::bail!("unknown price sink: {s}"),
+        }
+    }
+}
+
+/// When to query prices.
+#[derive(Debug, Clone, Copy)]
+pub struct QuerySchedule {
+    /// Interval between queries, or the longest one if adaptive.
+    pub period: Duration,
+
+    /// Shortest interval between queries, if intervals adapt to price changes.
+    pub min_period: Option<Duration>,
+
+    /// Random deviation of each interval, in percent of it.
+    pub jitter_percent: f64,
+}
+
+/// How much adaptive query intervals shrink per percent of price change.
+const ADAPTIVE_SENSITIVITY: f64 = 10.0;
+
+impl QuerySchedule {
+    /// Delay before the next query after a price change of `change_percent`: the interval from
+    /// [`QuerySchedule::compute_next_interval`] with random jitter, if enabled.
+    pub fn next_delay(&self, change_percent: Option<f64>) -> Duration {
+        let interval = self.compute_next_interval(change_percent);
+        if self.jitter_percent == 0.0 {
+            return interval;
+        }
+
+        let jitter = self.jitter_percent / 100.0;
+        interval.mul_f64(1.0 + rand::random_range(-jitter..=jitter))
+    }
+
+    /// Interval before the next query after a price change of `change_percent`.
+    ///
+    /// Without `min_period`, this is always `period`. Otherwise, `period` is divided by
+    /// `1 + 10 * |change_percent|` and clamped to at least `min_period`, so a 0.1% move halves the
+    /// interval, while unknown or no changes query every `period`.
+    pub fn compute_next_interval(&self, change_percent: Option<f64>) -> Duration {
+        let Some(min_period) = self.min_period else {
+            return self.period;
+        };
+
+        let change = change_percent.unwrap_or_default().abs();
+        if !change.is_finite() {
+            return min_period;
+        }
+        self.period
+            .div_f64(1.0 + change * ADAPTIVE_SENSITIVITY)
+            .max(min_period)
+    }
+}
+
+/// How to check and store queried prices.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PricePolicy {
+    /// Maximum USD price difference to skip storing a price, if deduplication is enabled.
+    pub dedup: Option<f64>,
+
+    /// Reject prices last updated longer ago than this.
+    pub max_age: Option<Duration>,
+
+    /// Query again to confirm USD price moves of more than this many percent.
+    pub max_jump_percent: Option<f64>,
+
+    /// How many prices to keep while they fail to be stored.
+    pub write_queue_capacity: usize,
+}
+
+/// App configuration.
+#[derive(Debug)]
+pub struct Config {
+    /// Postgres connection string. Only needed to store prices.
+    pub db_url: Option<String>,
+
+    /// Maximum number of Postgres connections.
+    pub db_max_connections: u32,
+
+    /// CoinGecko compatible simple price endpoint.
+    pub api_url: Url,
+
+    /// Header authenticating CoinGecko requests.
+    pub api_key: Option<(HeaderName, HeaderValue)>,
+
+    /// HTTP settings of price sources.
+    pub http: HttpConfig,
+
+    /// How to retry failed CoinGecko requests.
+    pub retry: RetryPolicy,
+
+    /// Maximum number of CoinGecko retries per minute.
+    pub retries_per_minute: Option<u32>,
+
+    /// Range of accepted CoinGecko USD prices.
+    pub price_bounds: RangeInclusive<f64>,
+
+    /// Currencies to query besides USD.
+    pub currencies: Vec<Currency>,
+
+    /// How to combine price sources.
+    pub aggregation: Aggregation,
+
+    /// Fall back to blockchain.com if CoinGecko fails.
+    pub fallback: bool,
+
+    /// How many sources must succeed in median aggregation.
+    pub min_sources: usize,
+
+    /// Maximum USD price difference of verified sources, in percent.
+    pub verify_tolerance_percent: f64,
+
+    /// How often to query prices.
+    pub query_period: Duration,
+
+    /// Shortest interval between queries, enabling intervals adapting to price changes.
+    pub query_min_period: Option<Duration>,
+
+    /// Random deviation of query intervals, in percent of the period.
+    pub query_jitter_percent: f64,
+
+    /// Maximum USD price difference to consider a price unchanged and skip storing it, if
+    /// deduplication is enabled.
+    pub dedup_epsilon: Option<f64>,
+
+    /// Store every queried price, even if unchanged.
+    pub store_every_tick: bool,
+
+    /// Store queried price info including market data as JSON.
+    pub store_raw: bool,
+
+    /// Store failed price queries.
+    pub store_errors: bool,
+
+    /// Round stored prices to this many decimal places.
+    pub store_decimals: Option<u32>,
+
+    /// Where to deliver queried prices, if set.
+    pub sinks: Option<Vec<SinkKind>>,
+
+    /// Reject prices last updated longer ago than this.
+    pub max_price_age: Option<Duration>,
+
+    /// Query again to confirm USD price moves of more than this many percent.
+    pub max_jump_percent: Option<f64>,
+
+    /// How many prices to keep while they fail to be stored.
+    pub write_queue_capacity: usize,
+
+    /// Report degraded health if no price query succeeded for this long.
+    pub health_max_age: Duration,
+
+    /// How long to wait for the price update in progress to finish on shutdown.
+    pub shutdown_timeout: Duration,
+
+    /// Alert if no price query succeeded for this long.
+    pub alert_after: Option<Duration>,
+
+    /// Slack compatible webhook to post alerts to.
+    pub alert_webhook: Option<Url>,
+
+    /// USD prices to serve instead of querying price APIs.
+    #[cfg(feature = "fixture")]
+    pub fixture: Option<Vec<f64>>,
+}
+
+impl Config {
+    /// Read configuration from environment variables, using defaults for missing ones.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut http = HttpConfig::default();
+        if let Some(secs) = var("PRICE_API_TIMEOUT_SECS")? {
+            http.timeout = Duration::from_secs(secs);
+        }
+        if let Some(proxy) = var("PRICE_API_PROXY")? {
+            http.proxies.push(proxy);
+        }
+        if let Ok(pool) = std::env::var("PRICE_API_PROXY_POOL") {
+            for proxy in pool.split(',') {
+                http.proxies.push(
+                    proxy
+                        .trim()
+                        .parse()
+                        .context("Invalid PRICE_API_PROXY_POOL")?,
+                );
+            }
+        }
+        if let Some(failures) = var("PRICE_API_ROTATE_PROXY_AFTER")? {
+            http.rotate_proxy_after = failures;
+        }
+        if let Some(millis) = var("PRICE_API_MIN_INTERVAL_MS")? {
+            http.min_interval = Duration::from_millis(millis);
+        }
+        if let Some(len) = var("PRICE_API_SNIPPET_LEN")? {
+            http.snippet_len = len;
+        }
+        if let Some(size) = var("PRICE_API_MAX_BODY_BYTES")? {
+            http.max_body_size = size;
+        }
+        if let Some(size) = var("PRICE_API_MIN_BODY_BYTES")? {
+            http.min_body_size = size;
+        }
+        if let Some(redirects) = var("PRICE_API_REDIRECTS")? {
+            http.redirects = redirects;
+        }
+        if let Some(max_idle) = var("PRICE_API_POOL_MAX_IDLE")? {
+            http.pool_max_idle_per_host = max_idle;
+        }
+        if let Some(secs) = var("PRICE_API_POOL_IDLE_TIMEOUT_SECS")? {
+            http.pool_idle_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+        if let Some(secs) = var("PRICE_API_TCP_KEEPALIVE_SECS")? {
+            http.tcp_keepalive = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+        if let Some(log_cookies) = var("PRICE_API_LOG_COOKIES")? {
+            http.log_cookies = log_cookies;
+        }
+        if let Ok(overrides) = std::env::var("PRICE_API_RESOLVE") {
+            for entry in overrides.split(',') {
+                let (host, ip) = entry
+                    .split_once('=')
+                    .context("Invalid PRICE_API_RESOLVE: expected <host>=<ip>")?;
+                let ip = ip.trim().parse().context("Invalid PRICE_API_RESOLVE")?;
+                http.resolve.push((host.trim().to_string(), ip));
+            }
+        }
+        if let Ok(version) = std::env::var("PRICE_API_MIN_TLS_VERSION") {
+            http.min_tls_version = Some(match version.as_str() {
+                "1.0" => tls::Version::TLS_1_0,
+                "1.1" => tls::Version::TLS_1_1,
+                "1.2" => tls::Version::TLS_1_2,
+                "1.3" => anyhow::bail!(
+                    "PRICE_API_MIN_TLS_VERSION 1.3 is not supported: the native TLS library can't \
+                     require TLS 1.3, use 1.2"
+                ),
+                _ => anyhow::bail!("Invalid PRICE_API_MIN_TLS_VERSION: {version}"),
+            });
+        }
+        if let Some(http2) = var("PRICE_API_HTTP2")? {
+            http.http2 = http2;
+        }
+        if let Some(accept) = var("PRICE_API_DANGER_ACCEPT_INVALID_CERTS")? {
+            http.danger_accept_invalid_certs = accept;
+        }
+        #[cfg(feature = "recording")]
+        {
+            use crate::recording::Recording;
+            http.recording = match (var("PRICE_API_RECORD")?, var("PRICE_API_REPLAY")?) {
+                (Some(_), Some(_)) => {
+                    anyhow::bail!("PRICE_API_RECORD and PRICE_API_REPLAY are mutually exclusive")
+                }
+                (Some(path), None) => Some(Recording::Record(path)),
+                (None, Some(path)) => Some(Recording::Replay(path)),
+                (None, None) => None,
+            };
+        }
+
+        let api_key = match var::<HeaderValue>("PRICE_API_KEY")? {
+            Some(mut key) => {
+                key.set_sensitive(true);
+                let header = var("PRICE_API_KEY_HEADER")?
+                    .unwrap_or(HeaderName::from_static(DEFAULT_API_KEY_HEADER));
+                Some((header, key))
+            }
+            None => None,
+        };
+
+        let mut retry = RetryPolicy::default();
+        if let Some(attempts) = var("PRICE_API_ATTEMPTS")? {
+            retry.max_attempts = attempts;
+        }
+
+        let currencies = match std::env::var("PRICE_CURRENCIES") {
+            Ok(currencies) => currencies
+                .split(',')
+                .map(Currency::from_str)
+                .collect::<Result<_, _>>()
+                .context("Invalid PRICE_CURRENCIES")?,
+            Err(_) => vec![],
+        };
+
+        let sinks = match std::env::var("PRICE_SINKS") {
+            Ok(sinks) => Some(
+                sinks
+                    .split(',')
+                    .map(SinkKind::from_str)
+                    .collect::<Result<_, _>>()
+                    .context("Invalid PRICE_SINKS")?,
+            ),
+            Err(_) => None,
+        };
+
+        let config = Self {
+            db_url: std::env::var("POSTGRES_CONN_STR")
+                .or_else(|_| std::env::var("DATABASE_URL"))
+                .ok(),
+            db_max_connections: var("POSTGRES_MAX_CONNECTIONS")?.unwrap_or(5),
+            api_url: var("PRICE_API_URL")?.unwrap_or_else(|| {
+                coingecko::DEFAULT_URL
+                    .parse()
+                    .expect("default URL is valid")
+            }),
+            api_key,
+            http,
+            retry,
+            retries_per_minute: var("PRICE_API_RETRIES_PER_MINUTE")?,
+            price_bounds: var("PRICE_MIN_USD")?.unwrap_or(*coingecko::DEFAULT_PRICE_BOUNDS.start())
+                ..=var("PRICE_MAX_USD")?.unwrap_or(*coingecko::DEFAULT_PRICE_BOUNDS.end()),
+            currencies,
+            aggregation: var("PRICE_AGGREGATION")?.unwrap_or(Aggregation::Fallback),
+            fallback: var("PRICE_FALLBACK")?.unwrap_or(true),
+            min_sources: var("PRICE_MIN_SOURCES")?.unwrap_or(1),
+            verify_tolerance_percent: var("PRICE_VERIFY_TOLERANCE_PERCENT")?.unwrap_or(1.0),
+            query_period: var("QUERY_PERIOD_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_QUERY_PERIOD),
+            query_min_period: var("QUERY_MIN_PERIOD_SECS")?.map(Duration::from_secs),
+            query_jitter_percent: var("QUERY_JITTER_PERCENT")?.unwrap_or(10.0),
+            dedup_epsilon: var("PRICE_DEDUP_EPSILON")?,
+            store_every_tick: var("PRICE_STORE_EVERY_TICK")?.unwrap_or(false),
+            store_raw: var("PRICE_STORE_RAW")?.unwrap_or(false),
+            store_errors: var("PRICE_STORE_ERRORS")?.unwrap_or(false),
+            store_decimals: match std::env::var("PRICE_STORE_DECIMALS").as_deref() {
+                Ok("none") => None,
+                Ok(_) => var("PRICE_STORE_DECIMALS")?,
+                Err(_) => Some(DEFAULT_STORE_DECIMALS),
+            },
+            max_price_age: var("PRICE_MAX_AGE_SECS")?.map(Duration::from_secs),
+            max_jump_percent: var("PRICE_MAX_JUMP_PERCENT")?,
+            sinks,
+            write_queue_capacity: var("PRICE_WRITE_QUEUE_CAPACITY")?
+                .unwrap_or(DEFAULT_WRITE_QUEUE_CAPACITY),
+            health_max_age: var("HEALTH_MAX_QUERY_AGE_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_HEALTH_MAX_AGE),
+            shutdown_timeout: var("SHUTDOWN_TIMEOUT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+            alert_after: var("ALERT_AFTER_SECS")?.map(Duration::from_secs),
+            alert_webhook: var("ALERT_WEBHOOK_URL")?,
+            #[cfg(feature = "fixture")]
+            fixture: match std::env::var("PRICE_FIXTURE") {
+                Ok(prices) => Some(
+                    prices
+                        .split(',')
+                        .map(|p| p.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .context("Invalid PRICE_FIXTURE")?,
+                ),
+                Err(_) => None,
+            },
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Check that settings make sense together.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.db_max_connections == 0 {
+            anyhow::bail!("Postgres max connections must not be zero");
+        }
+        if self.query_period.is_zero() {
+            anyhow::bail!("Query period must not be zero");
+        }
+        if self
+            .query_min_period
+            .is_some_and(|min| min.is_zero() || min > self.query_period)
+        {
+            anyhow::bail!(
+                "QUERY_MIN_PERIOD_SECS must be positive and not exceed QUERY_PERIOD_SECS"
+            );
+        }
+        if !(0.0..100.0).contains(&self.query_jitter_percent) {
+            anyhow::bail!("Query jitter must be at least 0% and less than 100%");
+        }
+        if self.price_bounds.is_empty() {
+            anyhow::bail!("PRICE_MIN_USD must not exceed PRICE_MAX_USD");
+        }
+        if self.http.timeout.is_zero() {
+            anyhow::bail!("Price API timeout must not be zero");
+        }
+        if self.http.rotate_proxy_after == 0 {
+            anyhow::bail!("PRICE_API_ROTATE_PROXY_AFTER must not be zero");
+        }
+        if self.verify_tolerance_percent.is_nan() || self.verify_tolerance_percent < 0.0 {
+            anyhow::bail!("Price verification tolerance must not be negative");
+        }
+        if self
+            .dedup_epsilon
+            .is_some_and(|epsilon| epsilon.is_nan() || epsilon < 0.0)
+        {
+            anyhow::bail!("Price dedup epsilon must not be negative");
+        }
+        if self
+            .max_jump_percent
+            .is_some_and(|percent| percent.is_nan() || percent <= 0.0)
+        {
+            anyhow::bail!("Max price jump must be positive");
+        }
+        if self.alert_webhook.is_some() && self.alert_after.is_none() {
+            anyhow::bail!("ALERT_WEBHOOK_URL requires ALERT_AFTER_SECS");
+        }
+        if self.write_queue_capacity == 0 {
+            anyhow::bail!("Price write queue capacity must not be zero");
+        }
+        Ok(())
+    }
+
+    /// When to query prices.
+    pub fn schedule(&self) -> QuerySchedule {
+        QuerySchedule {
+            period: self.query_period,
+            min_period: self.query_min_period,
+            jitter_percent: self.query_jitter_percent,
+        }
+    }
+
+    /// How to check and store queried prices.
+    pub fn price_policy(&self) -> PricePolicy {
+        PricePolicy {
+            dedup: self.dedup_epsilon.filter(|_| !self.store_every_tick),
+            max_age: self.max_price_age,
+            max_jump_percent: self.max_jump_percent,
+            write_queue_capacity: self.write_queue_capacity,
+        }
+    }
+
+    /// Postgres connection string, required to store prices.
+    pub fn db_url(&self) -> anyhow::Result<&str> {
+        self.db_url
+            .as_deref()
+            .context("Postgres connection string is not set: set POSTGRES_CONN_STR or DATABASE_URL")
+    }
+}
+
+/// Parse environment variable `name`, if set.
+fn var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid {name}: {e}")),
+        Err(_) => Ok(None),
+    }
+}
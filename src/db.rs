@@ -1,37 +1,148 @@
-use sqlx::types::chrono::NaiveDateTime;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use futures_util::TryStreamExt;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgRow};
+use sqlx::types::chrono::{NaiveDateTime, Utc};
 use sqlx::{PgPool, Row, migrate::Migrator, types::chrono::DateTime};
 
-use crate::{Price, PriceInfo};
+use futures_util::future::BoxFuture;
+use serde::Serialize;
+
+use crate::store::{PriceStore, QueryFailure};
+use crate::{Currency, Price, PriceInfo};
 
 static MIGRATOR: Migrator = sqlx::migrate!("pg/migrations");
 
+/// How long to wait for a pooled connection.
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Columns of prices pivoted by currency, to be used with `GROUP BY datetime HAVING` [`HAS_USD`].
+const PRICE_COLUMNS: &str = "datetime,
+    MAX(price) FILTER (WHERE currency = 'usd') AS usd,
+    MAX(price) FILTER (WHERE currency = 'eur') AS eur,
+    MAX(price) FILTER (WHERE currency = 'gbp') AS gbp";
+
+/// Condition of pivoted prices having USD value.
+const HAS_USD: &str = "COUNT(*) FILTER (WHERE currency = 'usd') > 0";
+
+/// Open, high, low and close prices of a time bucket.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Candle {
+    /// Unix timestamp of the bucket start.
+    pub start: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+}
+
+/// Lowest and highest prices of a time range.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Extremes {
+    pub min: f64,
+
+    /// Unix timestamp of the earliest lowest price.
+    pub min_at: u64,
+    pub max: f64,
+
+    /// Unix timestamp of the earliest highest price.
+    pub max_at: u64,
+}
+
+/// Outcome of a CSV import.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ImportStats {
+    /// Prices stored.
+    pub imported: usize,
+
+    /// Malformed rows skipped.
+    pub skipped: usize,
+}
+
 #[derive(Clone)]
 pub struct Db {
     pool: PgPool,
+
+    /// Store queried price info as JSON with USD prices.
+    store_raw: bool,
+
+    /// Round stored prices to this many decimal places.
+    decimals: Option<u32>,
 }
 
 impl Db {
-    pub async fn new(connection_string: String) -> anyhow::Result<Self> {
+    pub async fn new(connection_string: &str, max_connections: u32) -> anyhow::Result<Self> {
         Ok(Self {
-            pool: PgPool::connect(&connection_string).await?,
+            pool: make_pool(connection_string, max_connections).await?,
+            store_raw: false,
+            decimals: None,
         })
     }
 
-    /// Perform migrations.
-    pub async fn init(&self) -> anyhow::Result<()> {
-        MIGRATOR.run(&self.pool).await?;
-        Ok(())
+    /// Also store API responses prices were parsed from as JSON in the `raw` column.
+    pub fn store_raw(mut self, store_raw: bool) -> Self {
+        self.store_raw = store_raw;
+        self
+    }
+
+    /// Create or update the `prices` table. Already applied migrations are skipped,
+    /// so this is safe to call repeatedly.
+    pub async fn run_migrations(&self) -> anyhow::Result<()> {
+        MIGRATOR
+            .run(&self.pool)
+            .await
+            .context("Failed to apply migrations")
+    }
+
+    /// Round stored prices to `decimals` decimal places, half to even. Only the `raw` JSON
+    /// stored with [`Db::store_raw`] keeps full precision.
+    pub fn round_to(mut self, decimals: Option<u32>) -> Self {
+        self.decimals = decimals;
+        self
     }
 
-    /// Add price info to DB.
+    /// Add price info in all known currencies to DB.
+    /// A price with an already stored currency and timestamp replaces the old one, keeping its
+    /// stored JSON if the new price has none.
     pub async fn push_price(&self, price: Price) -> anyhow::Result<()> {
-        let millis = price.bitcoin.last_updated_at * 1_000;
-        let updated_dt = DateTime::from_timestamp_millis(millis as i64)
-            .ok_or_else(|| anyhow::Error::msg("Datetime overflow"))?;
+        let updated_dt = naive_datetime(price.bitcoin.last_updated_at)?;
+        let raw = price.raw.as_deref().filter(|_| self.store_raw);
+
+        let mut tx = self.pool.begin().await?;
+        for currency in Currency::ALL {
+            let Some(mut value) = price.bitcoin.price(currency) else {
+                continue;
+            };
+            if let Some(decimals) = self.decimals {
+                value = round(value, decimals);
+            }
+
+            let raw = raw.filter(|_| currency == Currency::Usd);
+            sqlx::query(
+                "INSERT INTO prices (currency, datetime, price, raw) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (currency, datetime)
+                DO UPDATE SET price = EXCLUDED.price, raw = COALESCE(EXCLUDED.raw, prices.raw)",
+            )
+            .bind(currency.code())
+            .bind(updated_dt)
+            .bind(value)
+            .bind(raw)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
 
-        sqlx::query("INSERT INTO prices (datetime, price) VALUES ($1, $2)")
-            .bind(updated_dt.naive_utc())
-            .bind(price.bitcoin.usd as i64)
+        Ok(())
+    }
+
+    /// Record that the price stored at `timestamp` was queried again unchanged at `seen_at`.
+    pub async fn touch_price(&self, timestamp: u64, seen_at: u64) -> anyhow::Result<()> {
+        sqlx::query("UPDATE prices SET last_seen = $2 WHERE datetime = $1")
+            .bind(naive_datetime(timestamp)?)
+            .bind(naive_datetime(seen_at)?)
             .execute(&self.pool)
             .await?;
 
@@ -41,27 +152,720 @@ impl Db {
     /// Get prices since specified timestamp.
     pub async fn prices_since(&self, timestamp: i64) -> anyhow::Result<Vec<Price>> {
         let millis = timestamp * 1_000;
-        let dt = DateTime::from_timestamp_millis(millis as i64)
+        let dt = DateTime::from_timestamp_millis(millis)
             .ok_or_else(|| anyhow::Error::msg("Datetime overflow"))?;
-        let rows = sqlx::query("SELECT * FROM prices WHERE datetime >= $1 ORDER BY datetime")
-            .bind(dt.naive_utc())
-            .fetch_all(&self.pool)
-            .await?;
+        let rows = sqlx::query(&format!(
+            "SELECT {PRICE_COLUMNS} FROM prices WHERE datetime >= $1
+            GROUP BY datetime HAVING {HAS_USD} ORDER BY datetime"
+        ))
+        .bind(dt.naive_utc())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(price_from_row).collect())
+    }
+
+    /// Get at most `limit` prices with timestamps in `from..=to`, oldest first.
+    pub async fn prices_between(
+        &self,
+        from: u64,
+        to: u64,
+        limit: u32,
+    ) -> anyhow::Result<Vec<Price>> {
+        if from > to {
+            return Ok(vec![]);
+        }
+
+        let rows = sqlx::query(&format!(
+            "SELECT {PRICE_COLUMNS} FROM prices WHERE datetime BETWEEN $1 AND $2
+            GROUP BY datetime HAVING {HAS_USD} ORDER BY datetime LIMIT $3"
+        ))
+        .bind(naive_datetime(from)?)
+        .bind(naive_datetime(to)?)
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(price_from_row).collect())
+    }
+
+    /// Get prices with timestamps in `from..=to` reduced to at most `max_points`, oldest first.
+    ///
+    /// The first and the last prices are kept, prices between them are split into even groups
+    /// which are averaged.
+    pub async fn downsampled(
+        &self,
+        from: u64,
+        to: u64,
+        max_points: u32,
+    ) -> anyhow::Result<Vec<Price>> {
+        if max_points < 2 {
+            anyhow::bail!("At least 2 points are required to keep the range ends");
+        }
+        if from > to {
+            return Ok(vec![]);
+        }
 
-        let mut prices = Vec::with_capacity(rows.len());
+        let rows = sqlx::query(&format!(
+            "WITH series AS (
+                SELECT {PRICE_COLUMNS} FROM prices WHERE datetime BETWEEN $1 AND $2
+                GROUP BY datetime HAVING {HAS_USD}
+            ), numbered AS (
+                SELECT *, ROW_NUMBER() OVER (ORDER BY datetime) AS n, COUNT(*) OVER () AS total
+                FROM series
+            ), bucketed AS (
+                SELECT *, CASE
+                    WHEN n = 1 THEN -1
+                    WHEN n = total THEN $3
+                    ELSE (n - 2) * ($3 - 2) / GREATEST(total - 2, 1)
+                END AS bucket
+                FROM numbered
+            )
+            SELECT
+                TIMESTAMP 'epoch'
+                    + ROUND(AVG(EXTRACT(EPOCH FROM datetime)))::DOUBLE PRECISION
+                    * INTERVAL '1 second' AS datetime,
+                AVG(usd) AS usd, AVG(eur) AS eur, AVG(gbp) AS gbp
+            FROM bucketed WHERE $3 > 2 OR n = 1 OR n = total
+            GROUP BY bucket ORDER BY bucket"
+        ))
+        .bind(naive_datetime(from)?)
+        .bind(naive_datetime(to)?)
+        .bind(i64::from(max_points))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(price_from_row).collect())
+    }
+
+    /// Mean of the last `window` stored USD prices, if at least `window` prices are stored.
+    pub async fn moving_average(&self, window: u32) -> anyhow::Result<Option<f64>> {
+        let average = sqlx::query_scalar(
+            "SELECT CASE WHEN COUNT(*) = $1 THEN AVG(price) END FROM (
+                SELECT price FROM prices WHERE currency = 'usd' ORDER BY datetime DESC LIMIT $1
+            ) AS recent",
+        )
+        .bind(i64::from(window))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(average)
+    }
+
+    /// Volatility of the last `window` stored USD prices, if at least `window` prices are stored.
+    ///
+    /// Volatility is the sample standard deviation of log returns `ln(price / previous price)`
+    /// between consecutive stored prices, not annualized. `window` must be at least 3.
+    pub async fn volatility(&self, window: u32) -> anyhow::Result<Option<f64>> {
+        let volatility = sqlx::query_scalar(
+            "SELECT CASE WHEN COUNT(*) = $1 - 1 THEN STDDEV_SAMP(log_return) END FROM (
+                SELECT LN(price / LAG(price) OVER (ORDER BY datetime)) AS log_return FROM (
+                    SELECT price, datetime FROM prices WHERE currency = 'usd'
+                    ORDER BY datetime DESC LIMIT $1
+                ) AS recent
+            ) AS returns WHERE log_return IS NOT NULL",
+        )
+        .bind(i64::from(window))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(volatility)
+    }
+
+    /// Time weighted average of USD prices with timestamps in `from..=to`, if any are stored.
+    ///
+    /// Each price is weighted by the time until the next one, or until `to` for the last one,
+    /// but at most by `max_gap`, so a price before downtime doesn't dominate. Prices stored only
+    /// at `to` are averaged evenly.
+    pub async fn twap(&self, from: u64, to: u64, max_gap: Duration) -> anyhow::Result<Option<f64>> {
+        if from > to {
+            return Ok(None);
+        }
+
+        let twap = sqlx::query_scalar(
+            "SELECT COALESCE(SUM(price * weight) / NULLIF(SUM(weight), 0), AVG(price)) FROM (
+                SELECT price, LEAST(
+                    EXTRACT(EPOCH FROM COALESCE(LEAD(datetime) OVER (ORDER BY datetime), $2)
+                        - datetime)::DOUBLE PRECISION,
+                    $3
+                ) AS weight
+                FROM prices WHERE currency = 'usd' AND datetime BETWEEN $1 AND $2
+            ) AS weighted",
+        )
+        .bind(naive_datetime(from)?)
+        .bind(naive_datetime(to)?)
+        .bind(max_gap.as_secs_f64())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(twap)
+    }
+
+    /// Lowest and highest USD prices with timestamps in `from..=to` and when they occurred, if
+    /// any prices are stored.
+    pub async fn price_extremes(&self, from: u64, to: u64) -> anyhow::Result<Option<Extremes>> {
+        let extremes: Option<(f64, i64, f64, i64)> = sqlx::query_as(
+            "SELECT low.price, EXTRACT(EPOCH FROM low.datetime)::BIGINT,
+                high.price, EXTRACT(EPOCH FROM high.datetime)::BIGINT
+            FROM (
+                SELECT price, datetime FROM prices
+                WHERE currency = 'usd' AND datetime BETWEEN $1 AND $2
+                ORDER BY price, datetime LIMIT 1
+            ) AS low, (
+                SELECT price, datetime FROM prices
+                WHERE currency = 'usd' AND datetime BETWEEN $1 AND $2
+                ORDER BY price DESC, datetime LIMIT 1
+            ) AS high",
+        )
+        .bind(naive_datetime(from)?)
+        .bind(naive_datetime(to)?)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(extremes.map(|(min, min_at, max, max_at)| Extremes {
+            min,
+            min_at: min_at as u64,
+            max,
+            max_at: max_at as u64,
+        }))
+    }
+
+    /// Gaps longer than `expected_interval` between USD prices with timestamps in `from..=to`,
+    /// as pairs of unix timestamps, oldest first. At most `limit` gaps are returned.
+    ///
+    /// A gap starts when a price was last seen, since unchanged prices are not stored again.
+    pub async fn coverage_gaps(
+        &self,
+        from: u64,
+        to: u64,
+        expected_interval: Duration,
+        limit: u32,
+    ) -> anyhow::Result<Vec<(u64, u64)>> {
+        if from > to {
+            return Ok(vec![]);
+        }
+
+        let gaps: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT EXTRACT(EPOCH FROM start)::BIGINT, EXTRACT(EPOCH FROM stop)::BIGINT FROM (
+                SELECT COALESCE(last_seen, datetime) AS start,
+                    LEAD(datetime) OVER (ORDER BY datetime) AS stop
+                FROM prices WHERE currency = 'usd' AND datetime BETWEEN $1 AND $2
+            ) AS spans
+            WHERE EXTRACT(EPOCH FROM stop - start) > $3
+            ORDER BY start LIMIT $4",
+        )
+        .bind(naive_datetime(from)?)
+        .bind(naive_datetime(to)?)
+        .bind(expected_interval.as_secs_f64())
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(gaps
+            .into_iter()
+            .map(|(start, stop)| (start as u64, stop as u64))
+            .collect())
+    }
+
+    /// Write prices with timestamps in `from..=to` to `writer` as CSV, oldest first.
+    ///
+    /// Rows are streamed from DB as they are written, so large ranges are not buffered in memory.
+    pub async fn export_csv(
+        &self,
+        from: u64,
+        to: u64,
+        mut writer: impl Write,
+    ) -> anyhow::Result<()> {
+        writeln!(writer, "timestamp,datetime,usd,eur,gbp")?;
+
+        let query = format!(
+            "SELECT {PRICE_COLUMNS} FROM prices WHERE datetime BETWEEN $1 AND $2
+            GROUP BY datetime HAVING {HAS_USD} ORDER BY datetime"
+        );
+        let mut rows = sqlx::query(&query)
+            .bind(naive_datetime(from)?)
+            .bind(naive_datetime(to)?)
+            .fetch(&self.pool);
+        while let Some(row) = rows.try_next().await? {
+            let datetime = row.get::<NaiveDateTime, _>(0).and_utc();
+            let price = price_from_row(&row).bitcoin;
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                datetime.timestamp(),
+                datetime.format("%Y-%m-%dT%H:%M:%SZ"),
+                price.usd,
+                csv_value(price.eur),
+                csv_value(price.gbp),
+            )?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Write prices with timestamps after `after` to `writer` as JSON lines, oldest first.
+    ///
+    /// Returns the timestamp of the last written price, or `after` if there are no newer prices,
+    /// to be used as `after` of the next export. Rows are streamed like in [`Db::export_csv`].
+    pub async fn export_since(&self, after: u64, mut writer: impl Write) -> anyhow::Result<u64> {
+        let query = format!(
+            "SELECT {PRICE_COLUMNS} FROM prices WHERE datetime > $1
+            GROUP BY datetime HAVING {HAS_USD} ORDER BY datetime"
+        );
+        let mut rows = sqlx::query(&query)
+            .bind(naive_datetime(after)?)
+            .fetch(&self.pool);
+        let mut cursor = after;
+        while let Some(row) = rows.try_next().await? {
+            let price = price_from_row(&row);
+            writeln!(writer, "{}", price.to_json())?;
+            cursor = price.bitcoin.last_updated_at;
+        }
+        writer.flush()?;
+
+        Ok(cursor)
+    }
+
+    /// Get candles of `currency` prices in `from..=to`, grouped into buckets of `bucket` length
+    /// aligned to unix epoch.
+    ///
+    /// Buckets without prices are skipped, or get a flat candle at the previous close
+    /// with `carry_forward`.
+    pub async fn candles(
+        &self,
+        currency: Currency,
+        from: u64,
+        to: u64,
+        bucket: Duration,
+        carry_forward: bool,
+    ) -> anyhow::Result<Vec<Candle>> {
+        let bucket = bucket.as_secs();
+        if bucket == 0 {
+            anyhow::bail!("Candle bucket must be at least a second");
+        }
+
+        let rows = sqlx::query(
+            "SELECT start,
+                (array_agg(price ORDER BY datetime))[1],
+                MAX(price),
+                MIN(price),
+                (array_agg(price ORDER BY datetime DESC))[1]
+            FROM (
+                SELECT price, datetime,
+                    (EXTRACT(EPOCH FROM datetime)::BIGINT / $4) * $4 AS start
+                FROM prices WHERE currency = $1 AND datetime BETWEEN $2 AND $3
+            ) AS bucketed
+            GROUP BY start ORDER BY start",
+        )
+        .bind(currency.code())
+        .bind(naive_datetime(from)?)
+        .bind(naive_datetime(to)?)
+        .bind(i64::try_from(bucket)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut candles: Vec<Candle> = Vec::with_capacity(rows.len());
         for row in rows {
-            let last_updated_at =
-                row.get::<NaiveDateTime, _>(0).and_utc().timestamp_millis() as u64 / 1000;
-            let usd = row.get::<i64, _>(1) as u64;
-
-            prices.push(Price {
-                bitcoin: PriceInfo {
-                    usd,
-                    last_updated_at,
-                },
-            });
+            let candle = Candle {
+                start: row.get::<i64, _>(0) as u64,
+                open: row.get(1),
+                high: row.get(2),
+                low: row.get(3),
+                close: row.get(4),
+            };
+            if carry_forward && let Some(&prev) = candles.last() {
+                let mut start = prev.start + bucket;
+                while start < candle.start {
+                    candles.push(Candle {
+                        start,
+                        open: prev.close,
+                        high: prev.close,
+                        low: prev.close,
+                        close: prev.close,
+                    });
+                    start += bucket;
+                }
+            }
+            candles.push(candle);
         }
 
-        Ok(prices)
+        Ok(candles)
+    }
+
+    /// Store prices read from CSV with `timestamp` and `usd` columns, as written by
+    /// [`Db::export_csv`]. Optional `eur` and `gbp` columns are stored too.
+    ///
+    /// Without a header row, columns are `timestamp,usd`. Malformed rows are skipped with a warning.
+    /// Prices with already stored timestamps are replaced.
+    pub async fn import_csv(&self, reader: impl BufRead) -> anyhow::Result<ImportStats> {
+        let mut columns = CsvColumns::default();
+        let mut stats = ImportStats::default();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if i == 0
+                && let Some(header) = CsvColumns::from_header(&line)
+            {
+                columns = header;
+                continue;
+            }
+
+            let price = columns
+                .parse(&line)
+                .and_then(|price| price.validate().map(|()| price).map_err(Into::into));
+            match price {
+                Ok(price) => {
+                    self.push_price(price).await?;
+                    stats.imported += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("Skipping CSV line {}: {e}.", i + 1);
+                    stats.skipped += 1;
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Get the most recent price known in `currency`, if any is stored.
+    pub async fn latest_price(&self, currency: Currency) -> anyhow::Result<Option<Price>> {
+        let row = sqlx::query(&format!(
+            "SELECT {PRICE_COLUMNS} FROM prices
+            WHERE datetime = (SELECT MAX(datetime) FROM prices WHERE currency = $1)
+            GROUP BY datetime HAVING {HAS_USD}"
+        ))
+        .bind(currency.code())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(price_from_row))
+    }
+
+    /// Store a failed price query.
+    pub async fn record_failure(&self, failure: &QueryFailure) -> anyhow::Result<()> {
+        sqlx::query("INSERT INTO errors (datetime, kind, status, message) VALUES ($1, $2, $3, $4)")
+            .bind(naive_datetime(failure.timestamp)?)
+            .bind(&failure.kind)
+            .bind(failure.status.map(|status| status as i16))
+            .bind(&failure.message)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Count a successful price query at `timestamp`, per minute.
+    pub async fn record_success(&self, timestamp: u64) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO query_successes (minute, count) VALUES (date_trunc('minute', $1), 1)
+            ON CONFLICT (minute) DO UPDATE SET count = query_successes.count + 1",
+        )
+        .bind(naive_datetime(timestamp)?)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Share of successful price queries with timestamps in `from..=to`, if any were recorded.
+    ///
+    /// Successes are counted per minute, so those in the minutes of `from` and `to` are counted
+    /// entirely.
+    pub async fn success_rate(&self, from: u64, to: u64) -> anyhow::Result<Option<f64>> {
+        let rate = sqlx::query_scalar(
+            "SELECT successes::DOUBLE PRECISION / NULLIF(successes + failures, 0) FROM (
+                SELECT
+                    (SELECT COALESCE(SUM(count), 0) FROM query_successes
+                    WHERE minute BETWEEN date_trunc('minute', $1) AND $2) AS successes,
+                    (SELECT COUNT(*) FROM errors WHERE datetime BETWEEN $1 AND $2) AS failures
+            ) AS counts",
+        )
+        .bind(naive_datetime(from)?)
+        .bind(naive_datetime(to)?)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(rate)
+    }
+
+    /// Up to `limit` most recent failed price queries, newest first.
+    pub async fn recent_errors(&self, limit: u32) -> anyhow::Result<Vec<QueryFailure>> {
+        let rows: Vec<(NaiveDateTime, String, Option<i16>, String)> = sqlx::query_as(
+            "SELECT datetime, kind, status, message FROM errors ORDER BY datetime DESC, id DESC
+            LIMIT $1",
+        )
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(datetime, kind, status, message)| QueryFailure {
+                timestamp: datetime.and_utc().timestamp() as u64,
+                kind,
+                status: status.map(|status| status as u16),
+                message,
+            })
+            .collect())
+    }
+
+    /// Most recent price known in `currency` and time since it was last updated.
+    ///
+    /// Prices updated in the future are reported with zero age.
+    pub async fn latest_price_with_age(
+        &self,
+        currency: Currency,
+    ) -> anyhow::Result<Option<(Price, Duration)>> {
+        let Some(price) = self.latest_price(currency).await? else {
+            return Ok(None);
+        };
+
+        let age_secs = Utc::now().timestamp() - price.bitcoin.last_updated_at as i64;
+        if age_secs < 0 {
+            tracing::warn!(
+                "Latest stored price is updated {}s in the future.",
+                -age_secs
+            );
+        }
+        Ok(Some((price, Duration::from_secs(age_secs.max(0) as u64))))
+    }
+}
+
+impl PriceStore for Db {
+    fn push_price(&self, price: Price) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(Db::push_price(self, price))
+    }
+
+    fn touch_price(&self, timestamp: u64, seen_at: u64) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(Db::touch_price(self, timestamp, seen_at))
+    }
+
+    fn latest_price(&self, currency: Currency) -> BoxFuture<'_, anyhow::Result<Option<Price>>> {
+        Box::pin(Db::latest_price(self, currency))
+    }
+}
+
+/// Connect pool of at most `max_connections` to `database_url`.
+pub async fn make_pool(database_url: &str, max_connections: u32) -> anyhow::Result<PgPool> {
+    let options =
+        PgConnectOptions::from_str(database_url).context("Invalid Postgres connection string")?;
+    PgPoolOptions::new()
+        .max_connections(max_connections)
+        .acquire_timeout(ACQUIRE_TIMEOUT)
+        .connect_with(options)
+        .await
+        .context("Failed to connect to Postgres")
+}
+
+/// Round `value` to `decimals` decimal places, half to even.
+fn round(value: f64, decimals: u32) -> f64 {
+    let scale = 10f64.powi(decimals as i32);
+    (value * scale).round_ties_even() / scale
+}
+
+/// Convert unix timestamp into DB datetime.
+fn naive_datetime(timestamp: u64) -> anyhow::Result<NaiveDateTime> {
+    i64::try_from(timestamp)
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .map(|dt| dt.naive_utc())
+        .ok_or_else(|| anyhow::Error::msg("Datetime overflow"))
+}
+
+/// Positions of price CSV columns.
+struct CsvColumns {
+    timestamp: usize,
+    usd: usize,
+    eur: Option<usize>,
+    gbp: Option<usize>,
+}
+
+impl Default for CsvColumns {
+    fn default() -> Self {
+        Self {
+            timestamp: 0,
+            usd: 1,
+            eur: None,
+            gbp: None,
+        }
+    }
+}
+
+impl CsvColumns {
+    /// Columns named by `line`, if it is a header with `timestamp` and `usd` columns.
+    fn from_header(line: &str) -> Option<Self> {
+        let names: Vec<_> = line.split(',').map(str::trim).collect();
+        let position = |name| names.iter().position(|n| *n == name);
+        Some(Self {
+            timestamp: position("timestamp")?,
+            usd: position("usd")?,
+            eur: position("eur"),
+            gbp: position("gbp"),
+        })
+    }
+
+    /// Parse price from CSV `line`.
+    fn parse(&self, line: &str) -> anyhow::Result<Price> {
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let field = |i: usize| {
+            fields
+                .get(i)
+                .copied()
+                .ok_or_else(|| anyhow::anyhow!("missing column {}", i + 1))
+        };
+        let optional = |i: Option<usize>| -> anyhow::Result<Option<f64>> {
+            match i.and_then(|i| fields.get(i)) {
+                Some(value) if !value.is_empty() => Ok(Some(value.parse()?)),
+                _ => Ok(None),
+            }
+        };
+
+        Ok(Price {
+            bitcoin: PriceInfo {
+                usd: field(self.usd)?.parse()?,
+                eur: optional(self.eur)?,
+                gbp: optional(self.gbp)?,
+                usd_market_cap: None,
+                usd_24h_vol: None,
+                usd_24h_change: None,
+                last_updated_at: field(self.timestamp)?.parse()?,
+            },
+            source: None,
+            change: None,
+            confidence: None,
+            raw: None,
+        })
+    }
+}
+
+/// CSV field of an optional price, empty if missing.
+fn csv_value(value: Option<f64>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Read price from a row of [`PRICE_COLUMNS`].
+fn price_from_row(row: &PgRow) -> Price {
+    let last_updated_at = row.get::<NaiveDateTime, _>(0).and_utc().timestamp_millis() as u64 / 1000;
+
+    Price {
+        bitcoin: PriceInfo {
+            usd: row.get("usd"),
+            eur: row.get("eur"),
+            gbp: row.get("gbp"),
+            usd_market_cap: None,
+            usd_24h_vol: None,
+            usd_24h_change: None,
+            last_updated_at,
+        },
+        source: None,
+        change: None,
+        confidence: None,
+        raw: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// Price of `usd` and `eur` at `timestamp`.
+    fn price(usd: f64, eur: Option<f64>, timestamp: u64) -> Price {
+        Price {
+            bitcoin: PriceInfo {
+                usd,
+                eur,
+                gbp: None,
+                usd_market_cap: None,
+                usd_24h_vol: None,
+                usd_24h_change: None,
+                last_updated_at: timestamp,
+            },
+            source: None,
+            change: None,
+            confidence: None,
+            raw: None,
+        }
+    }
+
+    /// Raw JSON stored with the USD price at `timestamp`.
+    async fn stored_raw(db: &Db, timestamp: u64) -> Option<serde_json::Value> {
+        sqlx::query_scalar("SELECT raw FROM prices WHERE currency = 'usd' AND datetime = $1")
+            .bind(naive_datetime(timestamp).unwrap())
+            .fetch_one(&db.pool)
+            .await
+            .unwrap()
+    }
+
+    /// Migrated DB of a pool created by `sqlx::test`.
+    fn db(pool: PgPool) -> Db {
+        Db {
+            pool,
+            store_raw: false,
+            decimals: None,
+        }
+    }
+
+    #[test]
+    fn prices_are_rounded_half_to_even() {
+        assert_eq!(round(63214.5678, 2), 63214.57);
+        assert_eq!(round(63214.5678, 0), 63215.0);
+        assert_eq!(round(0.125, 2), 0.12);
+        assert_eq!(round(0.135, 2), 0.14);
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn stored_price_is_read_back(pool: PgPool) {
+        let db = db(pool);
+        db.push_price(price(63000.5, Some(58000.0), 1700000000))
+            .await
+            .unwrap();
+        db.push_price(price(63100.0, None, 1700000060))
+            .await
+            .unwrap();
+        db.push_price(price(63200.25, None, 1700000060))
+            .await
+            .unwrap();
+
+        let latest = db.latest_price(Currency::Usd).await.unwrap().unwrap();
+        assert_eq!(latest.bitcoin.usd, 63200.25);
+        assert_eq!(latest.bitcoin.last_updated_at, 1700000060);
+
+        let prices = db.prices_between(1700000000, 1700000060, 10).await.unwrap();
+        let stored: Vec<_> = prices
+            .iter()
+            .map(|p| (p.bitcoin.last_updated_at, p.bitcoin.usd, p.bitcoin.eur))
+            .collect();
+        assert_eq!(
+            stored,
+            [
+                (1700000000, 63000.5, Some(58000.0)),
+                (1700000060, 63200.25, None)
+            ]
+        );
+    }
+
+    #[sqlx::test(migrations = "pg/migrations")]
+    async fn import_keeps_stored_raw_json(pool: PgPool) {
+        let db = db(pool).store_raw(true);
+        let raw = serde_json::json!({"bitcoin": {"usd": 63000.5, "last_updated_at": 1700000000}});
+        let mut queried = price(63000.5, None, 1700000000);
+        queried.raw = Some(Arc::new(raw.clone()));
+        db.push_price(queried).await.unwrap();
+
+        let stats = db
+            .import_csv("timestamp,usd\n1700000000,63001\n".as_bytes())
+            .await
+            .unwrap();
+
+        assert_eq!(stats.imported, 1);
+        let latest = db.latest_price(Currency::Usd).await.unwrap().unwrap();
+        assert_eq!(latest.bitcoin.usd, 63001.0);
+        assert_eq!(stored_raw(&db, 1700000000).await, Some(raw));
     }
 }
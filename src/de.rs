@@ -0,0 +1,61 @@
+use serde::{Deserialize, Deserializer, de::Error};
+
+/// Number, possibly encoded as a JSON string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString {
+    Number(f64),
+    String(String),
+}
+
+impl NumberOrString {
+    fn into_f64<E: Error>(self) -> Result<f64, E> {
+        match self {
+            Self::Number(n) => Ok(n),
+            Self::String(s) => s.trim().parse().map_err(E::custom),
+        }
+    }
+}
+
+/// Deserialize price given either as a JSON number or as a string like `"63214.57"`.
+pub fn number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    NumberOrString::deserialize(deserializer)?.into_f64()
+}
+
+/// Deserialize optional price given either as a JSON number or as a string.
+pub fn opt_number_or_string<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    Option::<NumberOrString>::deserialize(deserializer)?
+        .map(NumberOrString::into_f64)
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Quote {
+        #[serde(deserialize_with = "super::number_or_string")]
+        usd: f64,
+    }
+
+    #[test]
+    fn number_is_parsed() {
+        let quote: Quote = serde_json::from_str(r#"{"usd": 63214.57}"#).unwrap();
+        assert_eq!(quote.usd, 63214.57);
+    }
+
+    #[test]
+    fn numeric_string_is_parsed() {
+        let quote: Quote = serde_json::from_str(r#"{"usd": "63214.57"}"#).unwrap();
+        assert_eq!(quote.usd, 63214.57);
+    }
+
+    #[test]
+    fn non_numeric_string_fails() {
+        let result = serde_json::from_str::<Quote>(r#"{"usd": "n/a"}"#);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+use crate::Currency;
+
+/// Failure of a price query.
+#[derive(Debug)]
+pub enum QueryError {
+    /// Request could not be sent or its body could not be read.
+    Http(reqwest::Error),
+
+    /// Response body could not be read completely.
+    Body(reqwest::Error),
+
+    /// Response body is larger than the given number of bytes.
+    BodyTooLarge(usize),
+
+    /// Price API responded with an empty body.
+    EmptyBody,
+
+    /// Price API responded with an abnormally short body or an HTML page, e.g. during maintenance.
+    TargetUnavailable {
+        /// Beginning of the response body.
+        snippet: String,
+    },
+
+    /// Could not connect through the configured proxy.
+    Proxy(reqwest::Error),
+
+    /// Request did not complete in time.
+    Timeout(Duration),
+
+    /// Price API responded with a non-success status.
+    NonSuccessStatus {
+        status: StatusCode,
+
+        /// Beginning of the response body.
+        snippet: String,
+    },
+
+    /// Price API asked to slow down with 429 or 503 status.
+    RateLimited {
+        status: StatusCode,
+
+        /// Delay requested by the `Retry-After` header.
+        retry_after: Option<Duration>,
+    },
+
+    /// Price API redirected to a URL that was not followed, probably a bot challenge.
+    Redirected {
+        status: StatusCode,
+
+        /// Target of the redirect.
+        location: String,
+    },
+
+    /// Response is valid JSON without a BTC price.
+    NoPrice {
+        /// Beginning of the response body.
+        snippet: String,
+    },
+
+    /// Response body is not a valid price.
+    Parse {
+        error: serde_json::Error,
+
+        /// Beginning of the response body.
+        snippet: String,
+    },
+
+    /// Parsed USD price is outside of the accepted range.
+    OutOfBounds {
+        usd: f64,
+        bounds: RangeInclusive<f64>,
+    },
+
+    /// Source does not support the query, e.g. historical prices.
+    Unsupported,
+
+    /// Price was requested in a currency that is not supported.
+    UnsupportedCurrency(String),
+
+    /// Too few sources succeeded to aggregate their prices.
+    NotEnoughSources { succeeded: usize, required: usize },
+
+    /// USD prices `a` and `b` of two sources differ by more than the accepted tolerance.
+    SourcesDisagree { a: f64, b: f64 },
+
+    /// Query failed and was not retried because too many retries were made recently.
+    RetryBudgetExhausted(Box<QueryError>),
+}
+
+impl QueryError {
+    /// Short name of the failure category, e.g. `timeout`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Http(_) => "http",
+            Self::Body(_) => "body",
+            Self::BodyTooLarge(_) => "body_too_large",
+            Self::EmptyBody => "empty_body",
+            Self::TargetUnavailable { .. } => "target_unavailable",
+            Self::Proxy(_) => "proxy",
+            Self::Timeout(_) => "timeout",
+            Self::NonSuccessStatus { .. } => "non_success_status",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::Redirected { .. } => "redirected",
+            Self::NoPrice { .. } => "no_price",
+            Self::Parse { .. } => "parse",
+            Self::OutOfBounds { .. } => "out_of_bounds",
+            Self::Unsupported => "unsupported",
+            Self::UnsupportedCurrency(_) => "unsupported_currency",
+            Self::NotEnoughSources { .. } => "not_enough_sources",
+            Self::SourcesDisagree { .. } => "sources_disagree",
+            Self::RetryBudgetExhausted(_) => "retry_budget_exhausted",
+        }
+    }
+
+    /// HTTP status of the response that failed the query, if any.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Http(e) | Self::Body(e) | Self::Proxy(e) => e.status(),
+            Self::NonSuccessStatus { status, .. }
+            | Self::RateLimited { status, .. }
+            | Self::Redirected { status, .. } => Some(*status),
+            Self::RetryBudgetExhausted(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    /// Classify a failed request made with `timeout`, possibly through a proxy.
+    pub fn from_request(e: reqwest::Error, timeout: Duration, proxied: bool) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(timeout)
+        } else if proxied && e.is_connect() {
+            Self::Proxy(e)
+        } else {
+            Self::Http(e)
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(e) => write!(f, "HTTP request failed: {e}"),
+            Self::Body(e) => write!(f, "failed to read response body: {e}"),
+            Self::BodyTooLarge(limit) => {
+                write!(f, "response body is larger than {limit} bytes")
+            }
+            Self::EmptyBody => write!(f, "price API responded with an empty body"),
+            Self::TargetUnavailable { snippet } => {
+                write!(f, "price API is unavailable, responded with {snippet:?}")
+            }
+            Self::Proxy(e) => write!(f, "failed to connect through proxy: {e}"),
+            Self::Timeout(timeout) => write!(f, "request timed out after {timeout:?}"),
+            Self::NonSuccessStatus { status, snippet } => {
+                write!(f, "price API responded with {status}: {snippet:?}")
+            }
+            Self::RateLimited {
+                status,
+                retry_after: Some(delay),
+            } => write!(
+                f,
+                "price API responded with {status}, retry after {delay:?}"
+            ),
+            Self::RateLimited {
+                status,
+                retry_after: None,
+            } => write!(f, "price API responded with {status}"),
+            Self::Redirected { status, location } => write!(
+                f,
+                "price API redirected with {status} to {location:?}, probably a bot challenge"
+            ),
+            Self::NoPrice { snippet } => write!(f, "response has no BTC price: {snippet:?}"),
+            Self::Parse { error, snippet } => {
+                write!(f, "failed to parse price: {error}. Response: {snippet:?}")
+            }
+            Self::OutOfBounds { usd, bounds } => {
+                write!(
+                    f,
+                    "price {usd} USD is outside of the accepted range {bounds:?}"
+                )
+            }
+            Self::Unsupported => write!(f, "query is not supported by the price source"),
+            Self::UnsupportedCurrency(code) => {
+                let supported = Currency::ALL.map(Currency::code).join(", ");
+                write!(f, "unsupported currency {code:?}, supported: {supported}")
+            }
+            Self::NotEnoughSources {
+                succeeded,
+                required,
+            } => write!(
+                f,
+                "only {succeeded} price sources succeeded, {required} required"
+            ),
+            Self::SourcesDisagree { a, b } => {
+                write!(f, "price sources disagree: {a} USD and {b} USD")
+            }
+            Self::RetryBudgetExhausted(e) => write!(f, "{e}, retry budget exhausted"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(e) | Self::Body(e) | Self::Proxy(e) => Some(e),
+            Self::Parse { error, .. } => Some(error),
+            Self::RetryBudgetExhausted(e) => Some(e.as_ref()),
+            Self::Timeout(_)
+            | Self::BodyTooLarge(_)
+            | Self::EmptyBody
+            | Self::TargetUnavailable { .. }
+            | Self::NonSuccessStatus { .. }
+            | Self::RateLimited { .. }
+            | Self::Redirected { .. }
+            | Self::NoPrice { .. }
+            | Self::OutOfBounds { .. }
+            | Self::Unsupported
+            | Self::UnsupportedCurrency(_)
+            | Self::NotEnoughSources { .. }
+            | Self::SourcesDisagree { .. } => None,
+        }
+    }
+}
+
+/// Failure to query and store a price.
+#[derive(Debug)]
+pub enum StoreError {
+    /// Price could not be queried.
+    Query(QueryError),
+
+    /// Queried price is invalid.
+    Invalid(ValidationError),
+
+    /// Price could not be stored.
+    Store(anyhow::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Query(e) => write!(f, "failed to query price: {e}"),
+            Self::Invalid(e) => write!(f, "invalid price: {e}"),
+            Self::Store(e) => write!(f, "failed to store price: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Query(e) => Some(e),
+            Self::Invalid(e) => Some(e),
+            Self::Store(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+/// Inconsistency of a price that should not be stored.
+#[derive(Debug, Clone, Copy)]
+pub enum ValidationError {
+    /// Price in `currency` is not a finite positive number.
+    InvalidValue { currency: &'static str, value: f64 },
+
+    /// Update timestamp is zero.
+    ZeroTimestamp,
+
+    /// Update timestamp is too far in the future.
+    FutureTimestamp(u64),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidValue { currency, value } => {
+                write!(
+                    f,
+                    "price {value} {currency} is not a finite positive number"
+                )
+            }
+            Self::ZeroTimestamp => write!(f, "price update timestamp is zero"),
+            Self::FutureTimestamp(timestamp) => {
+                write!(f, "price update timestamp {timestamp} is in the future")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
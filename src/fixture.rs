@@ -0,0 +1,61 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures_util::future::BoxFuture;
+use sqlx::types::chrono::Utc;
+
+use crate::error::QueryError;
+use crate::source::PriceSource;
+use crate::{Price, PriceInfo};
+
+/// Source returning predefined USD prices without any network requests.
+///
+/// Each fetch advances to the next price, repeating the last one once all are returned.
+pub struct FixtureSource {
+    prices: Vec<f64>,
+    next: AtomicUsize,
+}
+
+impl FixtureSource {
+    /// Source of `prices`. Panics if `prices` is empty.
+    pub fn new(prices: Vec<f64>) -> Self {
+        assert!(
+            !prices.is_empty(),
+            "fixture source needs at least one price"
+        );
+        Self {
+            prices,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn next_price(&self) -> Price {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        let usd = self.prices[index.min(self.prices.len() - 1)];
+        Price {
+            bitcoin: PriceInfo {
+                usd,
+                eur: None,
+                gbp: None,
+                usd_market_cap: None,
+                usd_24h_vol: None,
+                usd_24h_change: None,
+                last_updated_at: Utc::now().timestamp() as u64,
+            },
+            source: Some(self.name()),
+            change: None,
+            confidence: None,
+            raw: None,
+        }
+    }
+}
+
+impl PriceSource for FixtureSource {
+    fn name(&self) -> &'static str {
+        "fixture"
+    }
+
+    fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+        let price = self.next_price();
+        Box::pin(async move { Ok(price) })
+    }
+}
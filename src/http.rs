@@ -0,0 +1,758 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::header::{
+    ETAG, HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LOCATION,
+    RETRY_AFTER, SET_COOKIE,
+};
+use reqwest::redirect;
+use reqwest::tls;
+use reqwest::{Client, IntoUrl, Proxy, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::error::QueryError;
+use crate::rate_limit::RateLimiter;
+#[cfg(feature = "recording")]
+use crate::recording::{Recorder, Recording, Replayer};
+
+/// Headers identifying a response version for conditional requests.
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+}
+
+impl Validators {
+    /// Validators of a response with `headers`.
+    fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            etag: headers.get(ETAG).cloned(),
+            last_modified: headers.get(LAST_MODIFIED).cloned(),
+        }
+    }
+
+    /// Whether no validators are known, so requests can't be conditional.
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Response to a conditional request.
+pub enum Fetched {
+    /// Response body has changed, or validators were not known.
+    Modified {
+        body: String,
+        validators: Validators,
+    },
+
+    /// Response is unchanged since the one validators were captured from.
+    NotModified,
+}
+
+/// Default timeout of a price request.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default minimum interval between requests.
+pub const DEFAULT_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default maximum size of a response body.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+/// Default number of consecutive failed requests after which to switch to the next proxy.
+pub const DEFAULT_ROTATE_PROXY_AFTER: u32 = 3;
+
+/// Default number of idle connections kept open to each price API host.
+pub const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 4;
+
+/// Default time idle connections are kept open.
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Default interval of TCP keepalive probes.
+pub const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// Default length of response body snippets attached to errors.
+pub const DEFAULT_SNIPPET_LEN: usize = 256;
+
+/// Maximum number of redirects followed by a request.
+const MAX_REDIRECTS: usize = 10;
+
+/// Which redirects to follow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    /// Follow all redirects.
+    FollowAll,
+
+    /// Follow only redirects to the origin of the requested URL.
+    #[default]
+    SameOrigin,
+}
+
+impl FromStr for RedirectPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::FollowAll),
+            "same-origin" => Ok(Self::SameOrigin),
+            _ => anyhow::bail!("unknown redirect policy: {s}"),
+        }
+    }
+}
+
+impl RedirectPolicy {
+    /// Policy of a reqwest client.
+    fn to_reqwest(self) -> redirect::Policy {
+        match self {
+            Self::FollowAll => redirect::Policy::limited(MAX_REDIRECTS),
+            Self::SameOrigin => redirect::Policy::custom(|attempt| {
+                let previous = attempt.previous();
+                if previous.len() > MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if previous
+                    .first()
+                    .is_some_and(|first| first.origin() != attempt.url().origin())
+                {
+                    attempt.stop()
+                } else {
+                    attempt.follow()
+                }
+            }),
+        }
+    }
+}
+
+/// HTTP settings shared by price API clients.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Timeout of a whole request, including reading the body.
+    pub timeout: Duration,
+
+    /// Proxies to send requests through, starting with the first one. Requests are sent
+    /// directly if empty.
+    pub proxies: Vec<Url>,
+
+    /// Switch to the next proxy after this many consecutive failed requests.
+    pub rotate_proxy_after: u32,
+
+    /// Minimum interval between requests, including retries.
+    pub min_interval: Duration,
+
+    /// Maximum length of response body snippets attached to errors.
+    pub snippet_len: usize,
+
+    /// Maximum size of a response body in bytes.
+    pub max_body_size: usize,
+
+    /// Bodies of success responses shorter than this many bytes mean the API is unavailable.
+    pub min_body_size: usize,
+
+    /// Headers sent with every request.
+    pub headers: HeaderMap,
+
+    /// Which redirects to follow. Redirects that are not followed fail requests.
+    pub redirects: RedirectPolicy,
+
+    /// Maximum number of idle connections kept open to each host for reuse.
+    pub pool_max_idle_per_host: usize,
+
+    /// How long idle connections are kept open, forever if `None`.
+    pub pool_idle_timeout: Option<Duration>,
+
+    /// Interval of TCP keepalive probes, disabled if `None`.
+    pub tcp_keepalive: Option<Duration>,
+
+    /// Log cookie values of responses instead of redacting them.
+    pub log_cookies: bool,
+
+    /// Addresses to connect to instead of resolving the given host names.
+    pub resolve: Vec<(String, IpAddr)>,
+
+    /// Minimum accepted TLS version, reqwest's default if `None`.
+    pub min_tls_version: Option<tls::Version>,
+
+    /// Offer HTTP/2 with ALPN. Only HTTP/1.1 is used if disabled.
+    pub http2: bool,
+
+    /// Accept invalid TLS certificates, e.g. of a local intercepting proxy.
+    ///
+    /// Unsafe: anyone on the network path can then forge price responses.
+    pub danger_accept_invalid_certs: bool,
+
+    /// Record responses or replay recorded ones.
+    #[cfg(feature = "recording")]
+    pub recording: Option<Recording>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            proxies: vec![],
+            rotate_proxy_after: DEFAULT_ROTATE_PROXY_AFTER,
+            min_interval: DEFAULT_MIN_INTERVAL,
+            snippet_len: DEFAULT_SNIPPET_LEN,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            min_body_size: 0,
+            headers: HeaderMap::new(),
+            redirects: RedirectPolicy::default(),
+            pool_max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+            pool_idle_timeout: Some(DEFAULT_POOL_IDLE_TIMEOUT),
+            tcp_keepalive: Some(DEFAULT_TCP_KEEPALIVE),
+            log_cookies: false,
+            resolve: vec![],
+            min_tls_version: None,
+            http2: true,
+            danger_accept_invalid_certs: false,
+            #[cfg(feature = "recording")]
+            recording: None,
+        }
+    }
+}
+
+/// Rate limited HTTP client of a JSON price API.
+pub struct ApiClient {
+    /// Client per proxy, or a single direct client.
+    clients: Vec<Client>,
+
+    /// Proxies of `clients`, without passwords.
+    proxies: Vec<Url>,
+
+    /// Index of the client in use.
+    active: AtomicUsize,
+
+    /// Failed requests since the last successful one or proxy switch.
+    failures: AtomicU32,
+    rotate_proxy_after: u32,
+    timeout: Duration,
+    proxied: bool,
+    snippet_len: usize,
+    max_body_size: usize,
+    min_body_size: usize,
+    log_cookies: bool,
+    limiter: RateLimiter,
+    #[cfg(feature = "recording")]
+    recorder: Option<Recorder>,
+    #[cfg(feature = "recording")]
+    replayer: Option<Replayer>,
+}
+
+impl ApiClient {
+    /// Client configured by `config`.
+    pub fn new(config: &HttpConfig) -> anyhow::Result<Self> {
+        if config.danger_accept_invalid_certs {
+            tracing::warn!(
+                "TLS certificate validation is disabled, price responses can be forged."
+            );
+        }
+
+        let clients = match config.proxies.as_slice() {
+            [] => vec![Self::client(config, None)?],
+            proxies => proxies
+                .iter()
+                .map(|url| {
+                    let proxy = Proxy::all(url.clone())
+                        .with_context(|| format!("Invalid proxy {}", redacted(url)))?;
+                    Self::client(config, Some(proxy))
+                })
+                .collect::<anyhow::Result<_>>()?,
+        };
+
+        Ok(Self {
+            clients,
+            proxies: config.proxies.iter().map(redacted).collect(),
+            active: AtomicUsize::new(0),
+            failures: AtomicU32::new(0),
+            rotate_proxy_after: config.rotate_proxy_after,
+            timeout: config.timeout,
+            proxied: !config.proxies.is_empty(),
+            snippet_len: config.snippet_len,
+            max_body_size: config.max_body_size,
+            min_body_size: config.min_body_size,
+            log_cookies: config.log_cookies,
+            limiter: RateLimiter::new(config.min_interval),
+            #[cfg(feature = "recording")]
+            recorder: match &config.recording {
+                Some(Recording::Record(path)) => Some(Recorder::open(path)?),
+                _ => None,
+            },
+            #[cfg(feature = "recording")]
+            replayer: match &config.recording {
+                Some(Recording::Replay(path)) => Some(Replayer::load(path)?),
+                _ => None,
+            },
+        })
+    }
+
+    /// Client configured by `config`, sending requests through `proxy`, if any.
+    fn client(config: &HttpConfig, proxy: Option<Proxy>) -> anyhow::Result<Client> {
+        let mut client = Client::builder()
+            .timeout(config.timeout)
+            .gzip(true)
+            .brotli(true)
+            .pool_max_idle_per_host(config.pool_max_idle_per_host)
+            .pool_idle_timeout(config.pool_idle_timeout)
+            .tcp_keepalive(config.tcp_keepalive)
+            .default_headers(config.headers.clone())
+            .redirect(config.redirects.to_reqwest());
+        if let Some(proxy) = proxy {
+            client = client.proxy(proxy);
+        }
+        for (host, ip) in &config.resolve {
+            // The port of the URL is used instead.
+            client = client.resolve(host, SocketAddr::new(*ip, 0));
+        }
+        if let Some(version) = config.min_tls_version {
+            client = client.min_tls_version(version);
+        }
+        if !config.http2 {
+            client = client.http1_only();
+        }
+        if config.danger_accept_invalid_certs {
+            client = client.danger_accept_invalid_certs(true);
+        }
+        Ok(client.build()?)
+    }
+
+    /// Send GET request with `query` parameters and parse JSON response.
+    pub async fn get_json<T: DeserializeOwned>(
+        &self,
+        url: impl IntoUrl,
+        query: &[(&str, &str)],
+    ) -> Result<T, QueryError> {
+        let body = self.get_body(url, query).await?;
+        parse_json(&body, self.snippet_len)
+    }
+
+    /// Send GET request with `query` parameters and read non-empty success response body.
+    ///
+    /// Fails with [`QueryError::TargetUnavailable`] if the body is shorter than the minimum body
+    /// size or is an HTML page, e.g. a maintenance notice, since price APIs respond with JSON.
+    ///
+    /// Switches to the next proxy after too many consecutive failures.
+    pub async fn get_body(
+        &self,
+        url: impl IntoUrl,
+        query: &[(&str, &str)],
+    ) -> Result<String, QueryError> {
+        match self
+            .get_body_if_modified(url, query, &Validators::default())
+            .await?
+        {
+            Fetched::Modified { body, .. } => Ok(body),
+            Fetched::NotModified => unreachable!("not modified responses require validators"),
+        }
+    }
+
+    /// Send GET request like [`ApiClient::get_body`], conditional on the response having changed
+    /// since the one `validators` were captured from.
+    pub async fn get_body_if_modified(
+        &self,
+        url: impl IntoUrl,
+        query: &[(&str, &str)],
+        validators: &Validators,
+    ) -> Result<Fetched, QueryError> {
+        let result = self.get_body_once(url, query, validators).await;
+        if result.is_ok() {
+            self.failures.store(0, Ordering::Relaxed);
+        } else if self.failures.fetch_add(1, Ordering::Relaxed) + 1 >= self.rotate_proxy_after
+            && self.clients.len() > 1
+        {
+            self.failures.store(0, Ordering::Relaxed);
+            let next = (self.active.load(Ordering::Relaxed) + 1) % self.clients.len();
+            self.active.store(next, Ordering::Relaxed);
+            tracing::warn!(
+                "{} consecutive price requests failed, switching to proxy {}.",
+                self.rotate_proxy_after,
+                self.proxies[next]
+            );
+        }
+        result
+    }
+
+    /// Send GET request once, see [`ApiClient::get_body_if_modified`].
+    async fn get_body_once(
+        &self,
+        url: impl IntoUrl,
+        query: &[(&str, &str)],
+        validators: &Validators,
+    ) -> Result<Fetched, QueryError> {
+        let mut request = self.active_client().get(url).query(query);
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let request = request.build().map_err(|e| self.request_error(e))?;
+        #[cfg(feature = "recording")]
+        if let Some(replayer) = &self.replayer {
+            let (status, headers, body) = replayer.response(request.url());
+            return self.check_response(status, &headers, body, validators);
+        }
+        #[cfg(feature = "recording")]
+        let url = redacted(request.url());
+
+        self.limiter.wait().await;
+        let response = self
+            .active_client()
+            .execute(request)
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        tracing::debug!(
+            "Price response from {}: {}, headers: {}",
+            response.url(),
+            response.status(),
+            headers_for_log(response.headers(), self.log_cookies)
+        );
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = self.read_body(response).await?;
+        tracing::trace!("Price response body: {body}");
+        #[cfg(feature = "recording")]
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&url, status, &headers, &body);
+        }
+
+        self.check_response(status, &headers, body, validators)
+    }
+
+    /// Check a response with `status`, `headers` and `body` to a request conditional on
+    /// `validators`, see [`ApiClient::get_body_if_modified`].
+    fn check_response(
+        &self,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: String,
+        validators: &Validators,
+    ) -> Result<Fetched, QueryError> {
+        if matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+        ) {
+            return Err(QueryError::RateLimited {
+                status,
+                retry_after: retry_after(headers),
+            });
+        }
+
+        if status == StatusCode::NOT_MODIFIED && !validators.is_empty() {
+            return Ok(Fetched::NotModified);
+        }
+
+        if status.is_redirection() {
+            let location = headers.get(LOCATION);
+            return Err(QueryError::Redirected {
+                status,
+                location: location
+                    .and_then(|l| l.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string(),
+            });
+        }
+
+        if !status.is_success() {
+            return Err(QueryError::NonSuccessStatus {
+                status,
+                snippet: snippet(&body, self.snippet_len),
+            });
+        }
+        let trimmed = body.trim();
+        if trimmed.is_empty() {
+            return Err(QueryError::EmptyBody);
+        }
+        if is_unavailable_page(trimmed, self.min_body_size) {
+            return Err(QueryError::TargetUnavailable {
+                snippet: snippet(trimmed, self.snippet_len),
+            });
+        }
+
+        Ok(Fetched::Modified {
+            body,
+            validators: Validators::from_headers(headers),
+        })
+    }
+
+    /// Maximum length of response body snippets attached to errors.
+    pub fn snippet_len(&self) -> usize {
+        self.snippet_len
+    }
+
+    /// Check that `url` responds with a success status within `timeout`.
+    pub async fn check(&self, url: impl IntoUrl, timeout: Duration) -> Result<(), QueryError> {
+        #[cfg(feature = "recording")]
+        if self.replayer.is_some() {
+            return Ok(());
+        }
+
+        let response = self
+            .active_client()
+            .get(url)
+            .timeout(timeout)
+            .send()
+            .await
+            .map_err(|e| QueryError::from_request(e, timeout, self.proxied))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(QueryError::NonSuccessStatus {
+                status,
+                snippet: snippet(&body, self.snippet_len),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Client of the proxy in use.
+    fn active_client(&self) -> &Client {
+        &self.clients[self.active.load(Ordering::Relaxed)]
+    }
+
+    /// Read response body, failing if it is larger than the maximum body size.
+    async fn read_body(&self, mut response: Response) -> Result<String, QueryError> {
+        let too_large = QueryError::BodyTooLarge(self.max_body_size);
+        if response
+            .content_length()
+            .is_some_and(|len| len > self.max_body_size as u64)
+        {
+            return Err(too_large);
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| self.body_error(e))? {
+            if body.len() + chunk.len() > self.max_body_size {
+                return Err(too_large);
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Classify failure to read response body.
+    fn body_error(&self, e: reqwest::Error) -> QueryError {
+        match self.request_error(e) {
+            QueryError::Http(e) => QueryError::Body(e),
+            e => e,
+        }
+    }
+
+    /// Classify request failure.
+    fn request_error(&self, e: reqwest::Error) -> QueryError {
+        QueryError::from_request(e, self.timeout, self.proxied)
+    }
+}
+
+/// `url` without password, for logging.
+fn redacted(url: &Url) -> Url {
+    let mut url = url.clone();
+    let _ = url.set_password(None);
+    url
+}
+
+/// Format `headers` for logging, redacting cookie values unless `show_cookies` is set.
+fn headers_for_log(headers: &HeaderMap, show_cookies: bool) -> String {
+    let fields: Vec<_> = headers
+        .iter()
+        .map(|(name, value)| {
+            if name == SET_COOKIE && !show_cookies {
+                let cookie = value.to_str().unwrap_or_default();
+                let name = cookie.split_once('=').map_or("?", |(name, _)| name);
+                return format!("{SET_COOKIE}: {name}=<redacted>");
+            }
+            format!("{name}: {value:?}")
+        })
+        .collect();
+    fields.join(", ")
+}
+
+/// Delay requested by `Retry-After` header, in either delta-seconds or HTTP-date form.
+/// A date in the past requests a zero delay.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&Utc) - Utc::now();
+    Some(delay.to_std().unwrap_or_default())
+}
+
+/// Parse JSON `body`, attaching up to `snippet_len` bytes of it to errors.
+pub fn parse_json<T: DeserializeOwned>(body: &str, snippet_len: usize) -> Result<T, QueryError> {
+    serde_json::from_str(body).map_err(|error| QueryError::Parse {
+        error,
+        snippet: snippet(body, snippet_len),
+    })
+}
+
+/// Whether non-empty trimmed success response `body` is not an API response but a page served
+/// instead, e.g. a maintenance notice: shorter than `min_body_size` bytes or HTML.
+fn is_unavailable_page(body: &str, min_body_size: usize) -> bool {
+    body.len() < min_body_size || body.starts_with('<')
+}
+
+/// Beginning of `body`, at most `max_len` bytes long.
+pub fn snippet(body: &str, max_len: usize) -> String {
+    let mut end = body.len().min(max_len);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    body[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, PRICE_BODY};
+
+    /// Headers with `Retry-After: value`.
+    fn retry_after_headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn retry_after_reads_seconds() {
+        let delay = retry_after(&retry_after_headers("120"));
+        assert_eq!(delay, Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn retry_after_reads_future_date() {
+        let date = Utc::now() + Duration::from_secs(120);
+        let value = date.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        let delay = retry_after(&retry_after_headers(&value)).unwrap();
+        assert!(
+            (Duration::from_secs(118)..=Duration::from_secs(120)).contains(&delay),
+            "{delay:?}"
+        );
+    }
+
+    #[test]
+    fn retry_after_past_date_is_zero_delay() {
+        let delay = retry_after(&retry_after_headers("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(delay, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_ignores_garbage() {
+        assert_eq!(retry_after(&retry_after_headers("soon")), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    /// Router of a mock API answering `/ping`.
+    fn ping_router() -> axum::Router {
+        axum::Router::new().route("/ping", axum::routing::get(|| async { "{}" }))
+    }
+
+    #[tokio::test]
+    async fn self_signed_certificate_is_rejected() {
+        let url = testing::serve_tls(ping_router()).await;
+        let api = ApiClient::new(&testing::http_config()).unwrap();
+        let error = api
+            .check(url.join("ping").unwrap(), Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, QueryError::Http(_)), "{error}");
+    }
+
+    #[tokio::test]
+    async fn invalid_certs_accept_self_signed_certificate() {
+        let url = testing::serve_tls(ping_router()).await;
+        let config = HttpConfig {
+            danger_accept_invalid_certs: true,
+            ..testing::http_config()
+        };
+        let api = ApiClient::new(&config).unwrap();
+        api.check(url.join("ping").unwrap(), Duration::from_secs(5))
+            .await
+            .unwrap();
+    }
+
+    /// Router of a mock API redirecting `/moved` to `location`.
+    fn redirect_router(location: String) -> axum::Router {
+        ping_router().route(
+            "/moved",
+            axum::routing::get(move || async move {
+                (StatusCode::FOUND, [(LOCATION.as_str(), location)])
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn same_origin_redirect_is_followed() {
+        let url = testing::serve(redirect_router("/ping".to_string())).await;
+        let api = ApiClient::new(&testing::http_config()).unwrap();
+        let body = api.get_body(url.join("moved").unwrap(), &[]).await.unwrap();
+        assert_eq!(body, "{}");
+    }
+
+    #[tokio::test]
+    async fn cross_origin_redirect_is_refused() {
+        let other = testing::serve(ping_router()).await;
+        let location = other.join("ping").unwrap().to_string();
+        let url = testing::serve(redirect_router(location.clone())).await;
+        let api = ApiClient::new(&testing::http_config()).unwrap();
+        let error = api
+            .get_body(url.join("moved").unwrap(), &[])
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, QueryError::Redirected { status, location: l }
+                if *status == StatusCode::FOUND && *l == location),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn http1_only_client_with_min_tls_version_queries_over_http1() {
+        let router = axum::Router::new().route(
+            "/version",
+            axum::routing::get(
+                |version: axum::http::Version| async move { format!("{version:?}") },
+            ),
+        );
+        let url = testing::serve_tls(router).await;
+        let config = HttpConfig {
+            min_tls_version: Some(tls::Version::TLS_1_2),
+            http2: false,
+            danger_accept_invalid_certs: true,
+            ..testing::http_config()
+        };
+        let api = ApiClient::new(&config).unwrap();
+        let version = api
+            .get_body(url.join("version").unwrap(), &[])
+            .await
+            .unwrap();
+        assert_eq!(version, "HTTP/1.1");
+    }
+
+    /// Page served by a CDN instead of the API during maintenance.
+    const MAINTENANCE_PAGE: &str = "<!DOCTYPE html>\n<html><head><title>Down for maintenance</title></head>\n<body><h1>We'll be back soon!</h1></body></html>";
+
+    #[test]
+    fn maintenance_page_is_unavailable() {
+        assert!(is_unavailable_page(MAINTENANCE_PAGE, 0));
+    }
+
+    #[test]
+    fn short_body_is_unavailable() {
+        assert!(is_unavailable_page("{}", 16));
+        assert!(!is_unavailable_page("{}", 2));
+    }
+
+    #[test]
+    fn price_response_is_available() {
+        assert!(!is_unavailable_page(PRICE_BODY, 16));
+    }
+}
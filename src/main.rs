@@ -1,25 +1,116 @@
+use alert::{DegradedAlert, watch_degraded};
+use anyhow::Context;
 use axum::Router;
-use axum::extract::State;
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::routing::get;
-use db::Db;
+use axum::routing::{get, post};
+use blockchain::Blockchain;
+use clap::Parser;
+use cli::{
+    BackfillArgs, Cli, Command, DaemonArgs, ExportArgs, ImportArgs, StoreArgs, SyncArgs, WatchArgs,
+};
+use coingecko::CoinGecko;
+use config::{Aggregation, Config, PricePolicy, QuerySchedule, SinkKind};
+use db::{Db, Extremes};
+use error::{QueryError, StoreError, ValidationError};
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
-use sqlx::types::Json;
-use sqlx::types::chrono::Utc;
+use sink::{NoopSink, PriceSink, StdoutSink, StoreSink};
+use source::{
+    CompositePriceSource, Confidence, LastSuccess, MedianPriceSource, PriceSource,
+    TrackedPriceSource, VerifiedPriceSource,
+};
+use sqlx::types::chrono::{DateTime, Utc};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::broadcast;
+use store::{PendingWrites, PriceStore, QueryFailure, QueryOutcome};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Notify, broadcast, mpsc, watch};
+use tokio_util::sync::CancellationToken;
 use tower_http::services::ServeDir;
 use tower_http::trace::DefaultMakeSpan;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod alert;
+mod blockchain;
+mod cli;
+mod clock;
+mod coingecko;
+mod config;
 mod db;
+mod de;
+mod error;
+#[cfg(feature = "fixture")]
+mod fixture;
+mod http;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "otel")]
+mod otel;
+mod rate_limit;
+#[cfg(feature = "recording")]
+mod recording;
+mod sink;
+mod source;
+mod store;
+#[cfg(test)]
+mod testing;
 
 /// State shared between app clients.
 struct AppState {
     db: Db,
     prices_rx: broadcast::Receiver<Price>,
+
+    /// Latest queried price, if any.
+    latest_rx: watch::Receiver<Option<Price>>,
+
+    /// When a price was last queried successfully.
+    last_success: Arc<LastSuccess>,
+
+    /// Report degraded health if no price query succeeded for this long.
+    health_max_age: Duration,
+
+    /// Settings reported by the debug endpoint.
+    settings: Arc<Settings>,
+
+    /// Notified to query the price immediately.
+    refresh: Arc<Notify>,
+}
+
+/// Daemon settings reported for diagnostics.
+#[derive(Debug, Serialize)]
+struct Settings {
+    /// CoinGecko compatible simple price endpoint, without query.
+    api_url: String,
+
+    /// Name of the price source.
+    source: &'static str,
+
+    /// Queried currencies besides USD.
+    currencies: Vec<&'static str>,
+
+    /// Seconds between price queries, before jitter.
+    query_period_secs: u64,
+}
+
+/// Live daemon state for diagnostics.
+#[derive(Debug, Serialize)]
+struct Snapshot {
+    #[serde(flatten)]
+    settings: Arc<Settings>,
+
+    /// Seconds since a price was last queried successfully.
+    last_success_age_secs: Option<u64>,
+
+    /// Last update timestamp of the latest queried price.
+    latest_price_at: Option<u64>,
+
+    /// Seconds since the daemon started.
+    uptime_secs: u64,
 }
 
 impl Clone for AppState {
@@ -27,41 +118,445 @@ impl Clone for AppState {
         Self {
             db: self.db.clone(),
             prices_rx: self.prices_rx.resubscribe(),
+            latest_rx: self.latest_rx.clone(),
+            last_success: self.last_success.clone(),
+            health_max_age: self.health_max_age,
+            settings: self.settings.clone(),
+            refresh: self.refresh.clone(),
         }
     }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                format!("{}=debug,tower_http=debug", env!("CARGO_CRATE_NAME")).into()
-            }),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    if let Err(e) = dotenv::dotenv() {
+    // Load `.env` first, so that it can configure tracing, e.g. with `RUST_LOG` or `OTEL_*`.
+    let dotenv = dotenv::dotenv();
+    init_tracing("debug");
+    if let Err(e) = dotenv {
         tracing::info!("Failed to load .env file: {e}");
     };
 
-    let conn_str = std::env::var("POSTGRES_CONN_STR")?;
-    let db = Db::new(conn_str).await?;
-    if let Err(e) = db.init().await {
-        // This may happen if db and tables already exist.
-        tracing::info!("Failed to apply migrations: {e}");
-    } else {
-        tracing::info!("Migrations applied.");
+    let cli = Cli::parse();
+    let mut config = Config::from_env()?;
+    if let Some(url) = cli.api_url {
+        config.api_url = url;
+    }
+    if !cli.currency.is_empty() {
+        config.currencies = cli.currency;
+    }
+
+    let result = match cli.command {
+        Some(Command::Once) => once(&config).await,
+        Some(Command::Watch(args)) => watch(&config, args).await,
+        Some(Command::Verify) => verify(&config).await,
+        Some(Command::Store(args)) => store(config, args).await,
+        Some(Command::Daemon(args)) => run_daemon(config, args).await,
+        Some(Command::Export(args)) => export(config, args).await,
+        Some(Command::Sync(args)) => sync(config, args).await,
+        Some(Command::Import(args)) => import(config, args).await,
+        Some(Command::Backfill(args)) => backfill(config, args).await,
+        None => run_daemon(config, DaemonArgs::default()).await,
+    };
+    #[cfg(feature = "otel")]
+    otel::shutdown();
+    result
+}
+
+/// Print the price queried once as JSON.
+async fn once(config: &Config) -> anyhow::Result<()> {
+    let source = price_source(config, false).await?;
+    let price = source.fetch().await?;
+    println!("{}", price.to_json());
+    Ok(())
+}
+
+/// Build price source described by `config`, recording metrics of its queries.
+///
+/// Checks CoinGecko API availability if `check_api` is set.
+async fn price_source(config: &Config, check_api: bool) -> anyhow::Result<Box<dyn PriceSource>> {
+    let source = build_price_source(config, check_api).await?;
+    #[cfg(feature = "metrics")]
+    let source: Box<dyn PriceSource> = Box::new(metrics::MeteredPriceSource(source));
+    Ok(source)
+}
+
+/// Build price source described by `config`, see [`price_source`].
+async fn build_price_source(
+    config: &Config,
+    check_api: bool,
+) -> anyhow::Result<Box<dyn PriceSource>> {
+    #[cfg(feature = "fixture")]
+    if let Some(prices) = &config.fixture {
+        return Ok(Box::new(fixture::FixtureSource::new(prices.clone())));
+    }
+
+    let mut coingecko = CoinGecko::builder()
+        .url(config.api_url.clone())
+        .http(config.http.clone())
+        .retry(config.retry)
+        .price_bounds(config.price_bounds.clone())
+        .currencies(config.currencies.clone());
+    if let Some(per_minute) = config.retries_per_minute {
+        coingecko = coingecko.retry_budget(per_minute);
+    }
+    if let Some((header, key)) = config.api_key.clone() {
+        coingecko = coingecko.auth_header(header, key);
+    }
+    let coingecko = Box::new(coingecko.build()?);
+    if check_api {
+        match coingecko.check().await {
+            Ok(()) => tracing::info!("CoinGecko API is reachable."),
+            Err(e) => tracing::warn!("CoinGecko API is unreachable, prices may be delayed: {e}."),
+        }
+    }
+
+    let source: Box<dyn PriceSource> = match config.aggregation {
+        Aggregation::Median => {
+            let sources: Vec<Box<dyn PriceSource>> =
+                vec![coingecko, Box::new(Blockchain::new(&config.http)?)];
+            Box::new(MedianPriceSource::new(sources, config.min_sources))
+        }
+        Aggregation::Verified => Box::new(VerifiedPriceSource::new(
+            coingecko,
+            Box::new(Blockchain::new(&config.http)?),
+            config.verify_tolerance_percent,
+        )),
+        Aggregation::Fallback => {
+            let mut source = CompositePriceSource::new(coingecko);
+            if config.fallback {
+                source = source.with_fallback(Box::new(Blockchain::new(&config.http)?));
+            }
+            Box::new(source)
+        }
     };
 
+    Ok(source)
+}
+
+/// Deliver prices queried periodically to configured sinks, by default printing them as JSON
+/// lines. Failures are logged.
+async fn watch(config: &Config, args: WatchArgs) -> anyhow::Result<()> {
+    let interval = args
+        .interval
+        .map(Duration::from_secs)
+        .unwrap_or(config.query_period);
+    if interval.is_zero() {
+        anyhow::bail!("Query period must not be zero");
+    }
+
+    let kinds = config.sinks.as_deref().unwrap_or(&[SinkKind::Stdout]);
+    let mut sinks: Vec<Box<dyn PriceSink>> = Vec::with_capacity(kinds.len());
+    for kind in kinds {
+        sinks.push(match kind {
+            SinkKind::Stdout => Box::new(StdoutSink),
+            SinkKind::Db => {
+                let db = Db::new(config.db_url()?, config.db_max_connections)
+                    .await?
+                    .store_raw(config.store_raw)
+                    .round_to(config.store_decimals);
+                db.run_migrations().await?;
+                Box::new(StoreSink(db))
+            }
+            SinkKind::None => Box::new(NoopSink),
+        });
+    }
+
+    let source = price_source(config, false).await?;
+    let mut prices = std::pin::pin!(source::price_stream(source, interval));
+    while let Some(result) = prices.next().await {
+        match result {
+            Ok(price) => sink::emit_all(&sinks, &price).await,
+            Err(e) => tracing::warn!("Failed to query price: {e}."),
+        }
+    }
+    Ok(())
+}
+
+/// Range of plausible USD prices.
+const PLAUSIBLE_PRICES: std::ops::RangeInclusive<f64> = 100.0..=10_000_000.0;
+
+/// Query the price once and check that the response parses to a plausible price.
+async fn verify(config: &Config) -> anyhow::Result<()> {
+    let source = price_source(config, false).await?;
+    let price = match source.fetch().await {
+        Ok(price) => price,
+        Err(e @ (QueryError::Parse { .. } | QueryError::NoPrice { .. })) => {
+            anyhow::bail!("Fetched price, but couldn't parse: {e}")
+        }
+        Err(e) => anyhow::bail!("Couldn't fetch price: {e}"),
+    };
+
+    if !PLAUSIBLE_PRICES.contains(&price.bitcoin.usd) {
+        anyhow::bail!(
+            "Parsed implausible price: {} USD, expected {PLAUSIBLE_PRICES:?}",
+            price.bitcoin.usd
+        );
+    }
+
+    println!("Price is {} USD.", price.bitcoin.usd);
+    Ok(())
+}
+
+/// Print stored prices to stdout as CSV.
+async fn export(mut config: Config, args: ExportArgs) -> anyhow::Result<()> {
+    if let Some(url) = args.db_url {
+        config.db_url = Some(url);
+    }
+
+    let db = Db::new(config.db_url()?, config.db_max_connections).await?;
+    let to = args.to.unwrap_or_else(|| Utc::now().timestamp() as u64);
+    let stdout = std::io::BufWriter::new(std::io::stdout());
+    db.export_csv(args.from, to, stdout).await
+}
+
+/// Print prices stored after the timestamp kept in the cursor file as JSON lines, then advance
+/// the cursor to the last printed price.
+async fn sync(mut config: Config, args: SyncArgs) -> anyhow::Result<()> {
+    if let Some(url) = args.db_url {
+        config.db_url = Some(url);
+    }
+
+    let after: u64 = match std::fs::read_to_string(&args.cursor) {
+        Ok(cursor) => cursor
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid sync cursor in {}", args.cursor.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e).context("Failed to read sync cursor"),
+    };
+
+    let db = Db::new(config.db_url()?, config.db_max_connections).await?;
+    let stdout = std::io::BufWriter::new(std::io::stdout());
+    let cursor = db.export_since(after, stdout).await?;
+    if cursor != after {
+        // Replace the cursor atomically, so an interrupted sync doesn't corrupt it.
+        let tmp = args.cursor.with_extension("tmp");
+        std::fs::write(&tmp, format!("{cursor}\n")).context("Failed to write sync cursor")?;
+        std::fs::rename(&tmp, &args.cursor).context("Failed to write sync cursor")?;
+    }
+    tracing::info!("Synced prices until {cursor}.");
+    Ok(())
+}
+
+/// Store prices read from CSV on stdin.
+async fn import(mut config: Config, args: ImportArgs) -> anyhow::Result<()> {
+    if let Some(url) = args.db_url {
+        config.db_url = Some(url);
+    }
+
+    let db = Db::new(config.db_url()?, config.db_max_connections).await?;
+    db.run_migrations().await?;
+    let stats = db.import_csv(std::io::stdin().lock()).await?;
+    tracing::info!(
+        "Imported {} prices, skipped {} malformed rows.",
+        stats.imported,
+        stats.skipped
+    );
+    Ok(())
+}
+
+/// Store daily historical prices, logging failed days.
+async fn backfill(mut config: Config, args: BackfillArgs) -> anyhow::Result<()> {
+    if let Some(url) = args.db_url {
+        config.db_url = Some(url);
+    }
+
+    let db = Db::new(config.db_url()?, config.db_max_connections).await?;
+    db.run_migrations().await?;
+    let source = price_source(&config, false).await?;
+    let to = args.to.unwrap_or_else(|| Utc::now().date_naive());
+    let mut stored = 0;
+    for date in args.from.iter_days().take_while(|date| *date <= to) {
+        let price = match source.fetch_at(date).await {
+            Ok(price) => price,
+            Err(QueryError::Unsupported) => {
+                anyhow::bail!("Price source doesn't keep history")
+            }
+            Err(e) => {
+                tracing::warn!("Failed to query {date} price: {e}.");
+                continue;
+            }
+        };
+        if let Err(e) = price.validate() {
+            tracing::warn!("Skipping invalid {date} price {price:?}: {e}.");
+            continue;
+        }
+        db.push_price(price.clone()).await?;
+        tracing::debug!("Stored {date} price: {price}.");
+        stored += 1;
+    }
+
+    tracing::info!("Stored {stored} historical prices.");
+    Ok(())
+}
+
+/// Query the price once, store it and print the stored price.
+async fn store(mut config: Config, args: StoreArgs) -> anyhow::Result<()> {
+    if let Some(url) = args.db_url {
+        config.db_url = Some(url);
+    }
+
+    let db = Db::new(config.db_url()?, config.db_max_connections)
+        .await?
+        .store_raw(config.store_raw)
+        .round_to(config.store_decimals);
+    db.run_migrations().await?;
+    let source = price_source(&config, false).await?;
+    let stored = scrape_and_store(&source, &db, config.price_policy().dedup).await?;
+    if stored.deduplicated {
+        tracing::info!("Price unchanged since {}, not stored again.", stored.price);
+    }
+    println!("{}", stored.price.to_json());
+    Ok(())
+}
+
+/// Outcome of [`scrape_and_store`].
+struct Stored {
+    /// Stored price.
+    price: Price,
+
+    /// Queried price was unchanged, `price` is the previously stored one.
+    deduplicated: bool,
+}
+
+/// Query and validate the price, then store it.
+///
+/// With `dedup`, a price differing from the latest stored one by at most `dedup` USD is not
+/// stored, only the time the stored price was last seen is updated.
+async fn scrape_and_store(
+    source: &impl PriceSource,
+    db: &impl PriceStore,
+    dedup: Option<f64>,
+) -> Result<Stored, StoreError> {
+    let price = source.fetch().await.map_err(StoreError::Query)?;
+    price.validate().map_err(StoreError::Invalid)?;
+
+    if let Some(epsilon) = dedup
+        && let Some(prev) = db
+            .latest_price(Currency::Usd)
+            .await
+            .map_err(StoreError::Store)?
+        && is_unchanged(epsilon, &prev, &price)
+    {
+        db.touch_price(prev.bitcoin.last_updated_at, price.bitcoin.last_updated_at)
+            .await
+            .map_err(StoreError::Store)?;
+        return Ok(Stored {
+            price: prev,
+            deduplicated: true,
+        });
+    }
+
+    db.push_price(price.clone())
+        .await
+        .map_err(StoreError::Store)?;
+    Ok(Stored {
+        price,
+        deduplicated: false,
+    })
+}
+
+/// Periodically query, store and serve prices until shutdown.
+async fn run_daemon(mut config: Config, args: DaemonArgs) -> anyhow::Result<()> {
+    if let Some(url) = args.db_url {
+        config.db_url = Some(url);
+    }
+    if let Some(secs) = args.interval {
+        config.query_period = Duration::from_secs(secs);
+    }
+    config.validate()?;
+    let mut sinks: Vec<Box<dyn PriceSink>> = vec![];
+    for kind in config.sinks.iter().flatten() {
+        sinks.push(match kind {
+            SinkKind::Stdout => Box::new(StdoutSink),
+            SinkKind::Db => {
+                anyhow::bail!("The daemon always stores prices, remove db from PRICE_SINKS")
+            }
+            SinkKind::None => Box::new(NoopSink),
+        });
+    }
+
+    let db = Db::new(config.db_url()?, config.db_max_connections)
+        .await?
+        .store_raw(config.store_raw)
+        .round_to(config.store_decimals);
+    db.run_migrations().await?;
+    tracing::info!("Migrations applied.");
+
+    let last_success = Arc::new(LastSuccess::new());
+    let mut source =
+        TrackedPriceSource::new(price_source(&config, true).await?, last_success.clone());
+    if config.store_errors {
+        let (outcomes_tx, outcomes_rx) = mpsc::channel(1024);
+        source = source.with_outcomes(outcomes_tx);
+        tokio::spawn(store_outcomes(outcomes_rx, db.clone()));
+    }
+    let mut api_url = config.api_url.clone();
+    // The query may carry an API key.
+    api_url.set_query(None);
+    let settings = Arc::new(Settings {
+        api_url: api_url.to_string(),
+        source: source.name(),
+        currencies: config.currencies.iter().map(|c| c.code()).collect(),
+        query_period_secs: config.query_period.as_secs(),
+    });
+
     let db_clone = db.clone();
     let (tx, rx) = broadcast::channel(1024);
-    tokio::spawn(prices_update_routine(db_clone, tx));
+    let (latest_tx, latest_rx) = watch::channel(None);
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+    if let Some(threshold) = config.alert_after {
+        let alert = DegradedAlert::logging(threshold, config.alert_webhook.clone());
+        tokio::spawn(watch_degraded(
+            alert,
+            last_success.clone(),
+            shutdown.clone(),
+        ));
+    }
+    let refresh = Arc::new(Notify::new());
+    let mut routine = tokio::spawn(prices_update_routine(
+        source,
+        db_clone,
+        Publisher {
+            tx,
+            latest_tx,
+            sinks,
+        },
+        config.schedule(),
+        config.price_policy(),
+        refresh.clone(),
+        shutdown.clone(),
+    ));
 
-    let state = AppState { db, prices_rx: rx };
-    let router = Router::new()
+    let state = AppState {
+        db,
+        prices_rx: rx,
+        latest_rx,
+        last_success,
+        health_max_age: config.health_max_age,
+        settings,
+        refresh,
+    };
+    let router = Router::new();
+    #[cfg(feature = "metrics")]
+    let router = router.route("/metrics", get(metrics));
+    let router = router
         .route("/price_ws", get(price_ws))
+        .route("/prices", get(prices))
+        .route("/candles", get(candles))
+        .route("/stats/moving_average", get(moving_average))
+        .route("/stats/volatility", get(volatility))
+        .route("/stats/twap", get(twap))
+        .route("/stats/gaps", get(coverage_gaps))
+        .route("/stats/extremes", get(price_extremes))
+        .route("/stats/success_rate", get(success_rate))
+        .route("/price", get(latest_price))
+        .route("/stored_price", get(stored_price))
+        .route("/health", get(health))
+        .route("/errors", get(recent_errors))
+        .route("/refresh", post(refresh_price))
+        .route("/debug", get(debug))
         .fallback_service(ServeDir::new("assets"))
         .layer(
             TraceLayer::new_for_http()
@@ -75,45 +570,731 @@ async fn main() -> anyhow::Result<()> {
         .expect("failed to init TCP listener");
 
     tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, router.layer(TraceLayer::new_for_http())).await?;
+    axum::serve(listener, router.layer(TraceLayer::new_for_http()))
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
+    match tokio::time::timeout(config.shutdown_timeout, &mut routine).await {
+        Ok(stopped) => stopped?,
+        Err(_) => {
+            routine.abort();
+            tracing::warn!(
+                "Price update routine didn't stop in {:?}, forced shutdown.",
+                config.shutdown_timeout
+            );
+        }
+    }
 
     Ok(())
 }
 
-/// How often to query prices from a service.
-const QUERY_PERIOD: Duration = Duration::from_secs(5);
+/// Store outcomes of price queries received from `outcomes` until all senders are dropped.
+async fn store_outcomes(mut outcomes: mpsc::Receiver<QueryOutcome>, db: Db) {
+    while let Some(outcome) = outcomes.recv().await {
+        let result = match &outcome {
+            QueryOutcome::Success(timestamp) => db.record_success(*timestamp).await,
+            QueryOutcome::Failure(failure) => db.record_failure(failure).await,
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to store price query outcome: {e}.");
+        }
+    }
+}
+
+/// Log this crate and HTTP traces at `level`, unless `RUST_LOG` says otherwise. With the
+/// `otel` feature, the traces are also exported over OTLP.
+///
+/// Does nothing if a global subscriber is already installed.
+fn init_tracing(level: &str) {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        format!("{}={level},tower_http={level}", env!("CARGO_CRATE_NAME")).into()
+    });
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr));
+    #[cfg(feature = "otel")]
+    let registry = registry.with(match otel::layer() {
+        Ok(layer) => Some(layer),
+        Err(e) => {
+            eprintln!("Failed to set up OpenTelemetry export: {e}");
+            None
+        }
+    });
+    let _ = registry.try_init();
+}
 
-async fn prices_update_routine(db: Db, tx: broadcast::Sender<Price>) {
-    let mut last_timestamp = Option::<u64>::None;
+/// Cancel `shutdown` on Ctrl+C or SIGTERM.
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for Ctrl+C: {e}.");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {e}.");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutting down.");
+    shutdown.cancel();
+}
+
+/// Query prices on `schedule`, store and publish new ones. Failures are logged and skipped.
+///
+/// Prices are checked and stored according to `policy`. Notifying `refresh` queries the price
+/// immediately.
+///
+/// Prices failing to be stored are queued and stored once the store recovers.
+///
+/// Stops once `shutdown` is cancelled, after finishing the update in progress and storing
+/// queued prices.
+async fn prices_update_routine(
+    source: impl PriceSource,
+    db: impl PriceStore,
+    publisher: Publisher,
+    schedule: QuerySchedule,
+    policy: PricePolicy,
+    refresh: Arc<Notify>,
+    shutdown: CancellationToken,
+) {
+    let mut last_stored = match db.latest_price(Currency::Usd).await {
+        Ok(price) => price,
+        Err(e) => {
+            tracing::info!("Failed to read latest price: {e}.");
+            None
+        }
+    };
+    let mut last_timestamp = last_stored.as_ref().map(|p| p.bitcoin.last_updated_at);
+    let mut pending = PendingWrites::new(policy.write_queue_capacity);
     loop {
-        if let Err(e) = update_price(&db, &tx, &mut last_timestamp).await {
+        let update = update_price(
+            &source,
+            &db,
+            &publisher,
+            &mut last_timestamp,
+            &mut last_stored,
+            &mut pending,
+            &policy,
+        );
+        if let Err(e) = update.await {
             tracing::warn!("Routine failure: {e}");
         }
-        tokio::time::sleep(QUERY_PERIOD).await;
+
+        let change_percent = publisher
+            .latest()
+            .and_then(|price| price.change)
+            .map(|change| change.percent);
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(schedule.next_delay(change_percent)) => {}
+            _ = refresh.notified() => tracing::debug!("Price refresh requested.")
+        }
+    }
+
+    if let Err(e) = pending.flush(&db).await {
+        tracing::warn!("Lost {} queued prices: {e}.", pending.len());
     }
+    tracing::info!("Price update routine stopped.");
 }
 
+/// Query price, store and publish it if it is new.
+///
+/// A price differing from the last stored one by at most `policy.dedup` USD is not stored,
+/// only the time it was last seen is updated. Prices queued in `pending` are stored first.
 async fn update_price(
-    db: &Db,
-    tx: &broadcast::Sender<Price>,
+    source: &impl PriceSource,
+    db: &impl PriceStore,
+    publisher: &Publisher,
     last_timestamp: &mut Option<u64>,
+    last_stored: &mut Option<Price>,
+    pending: &mut PendingWrites,
+    policy: &PricePolicy,
 ) -> anyhow::Result<()> {
-    let price = match dbg!(query_price().await) {
-        Ok(p) => p,
+    let prev = publisher.latest();
+    let price = fetch_checked(source, prev.as_ref(), policy).await?;
+    if let Err(e) = price.validate() {
+        anyhow::bail!("Skipping invalid price {price:?}: {e}.");
+    }
+
+    if last_timestamp.unwrap_or_default() < price.bitcoin.last_updated_at {
+        let mut price = price;
+        price.change = prev.map(|prev| compute_change(&prev, &price));
+        publisher.publish(price.clone()).await;
+        *last_timestamp = Some(price.bitcoin.last_updated_at);
+
+        let unchanged = match (policy.dedup, last_stored.as_ref()) {
+            (Some(epsilon), Some(stored)) => is_unchanged(epsilon, stored, &price),
+            _ => false,
+        };
+        if unchanged {
+            if let Err(e) = pending.flush(db).await {
+                anyhow::bail!("Failed to store {} queued prices: {e}.", pending.len());
+            }
+            let stored_at = last_stored
+                .as_ref()
+                .map(|p| p.bitcoin.last_updated_at)
+                .unwrap_or_default();
+            if let Err(e) = db
+                .touch_price(stored_at, price.bitcoin.last_updated_at)
+                .await
+            {
+                anyhow::bail!("Failed to update price last seen time: {e}.");
+            }
+        } else {
+            pending.push(price.clone());
+            *last_stored = Some(price);
+            if let Err(e) = pending.flush(db).await {
+                anyhow::bail!("Failed to update price, {} queued: {e}.", pending.len());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether USD `price` differs from `stored` by at most `epsilon`, compared in cents.
+fn is_unchanged(epsilon: f64, stored: &Price, price: &Price) -> bool {
+    let epsilon_cents = (epsilon * 100.0).round_ties_even() as i64;
+    (price.bitcoin.usd_cents() - stored.bitcoin.usd_cents()).abs() <= epsilon_cents
+}
+
+/// Channels publishing queried prices to app clients and other sinks.
+struct Publisher {
+    /// Every new price.
+    tx: broadcast::Sender<Price>,
+
+    /// Latest price.
+    latest_tx: watch::Sender<Option<Price>>,
+
+    /// Other destinations of every new price.
+    sinks: Vec<Box<dyn PriceSink>>,
+}
+
+impl Publisher {
+    /// Latest published price, if any.
+    fn latest(&self) -> Option<Price> {
+        self.latest_tx.borrow().clone()
+    }
+
+    /// Send `price` to app clients and sinks, logging failures of sinks.
+    async fn publish(&self, price: Price) {
+        self.latest_tx.send_replace(Some(price.clone()));
+        let _ = self.tx.send(price.clone());
+        sink::emit_all(&self.sinks, &price).await;
+    }
+}
+
+/// Query price, rejecting prices older than `policy.max_age`.
+///
+/// Queries again if the USD price moved since `prev` by more than `policy.max_jump_percent`,
+/// accepting the new price only if the second query confirms it, differing from the first one by
+/// at most `policy.max_jump_percent` too.
+async fn fetch_checked(
+    source: &impl PriceSource,
+    prev: Option<&Price>,
+    policy: &PricePolicy,
+) -> anyhow::Result<Price> {
+    let mut price = fetch(source).await?;
+    if let (Some(max_jump), Some(prev)) = (policy.max_jump_percent, prev) {
+        let jump = compute_change(prev, &price).percent.abs();
+        if jump > max_jump {
+            tracing::warn!("Price jumped by {jump:.2}%, querying again to confirm.");
+            let confirmation = fetch(source).await?;
+            let disagreement = compute_change(&price, &confirmation).percent.abs();
+            if disagreement > max_jump {
+                anyhow::bail!(
+                    "Unconfirmed price jump: {} USD queried again as {} USD.",
+                    price.bitcoin.usd,
+                    confirmation.bitcoin.usd
+                );
+            }
+            price = confirmation;
+        }
+    }
+
+    if let Some(max_age) = policy.max_age {
+        let age = Utc::now().timestamp() - price.bitcoin.last_updated_at as i64;
+        if age > max_age.as_secs() as i64 {
+            anyhow::bail!("Stale price: last updated {age}s ago.");
+        }
+    }
+
+    Ok(price)
+}
+
+/// Query price, logging it.
+async fn fetch(source: &impl PriceSource) -> anyhow::Result<Price> {
+    match source.fetch().await {
+        Ok(p) => {
+            tracing::debug!("Queried price: {p}.");
+            Ok(p)
+        }
         Err(e) => {
             anyhow::bail!("Failed to query price: {e}.");
         }
+    }
+}
+
+/// Latest queried price. Responds with 503 until the first price is queried.
+async fn latest_price(State(state): State<AppState>) -> Result<axum::Json<Price>, StatusCode> {
+    let latest = state.latest_rx.borrow().clone();
+    latest
+        .map(axum::Json)
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)
+}
+
+/// Stored price request.
+#[derive(Deserialize)]
+struct StoredPriceQuery {
+    /// Currency the price must be known in. USD by default.
+    currency: Option<Currency>,
+}
+
+/// Stored price with its age.
+#[derive(Serialize)]
+struct StoredPrice {
+    #[serde(flatten)]
+    price: Price,
+
+    /// Seconds since the price was last updated.
+    age_secs: u64,
+}
+
+/// Most recent stored price and its age. Responds with 404 if no price is stored.
+async fn stored_price(
+    State(state): State<AppState>,
+    Query(query): Query<StoredPriceQuery>,
+) -> Result<axum::Json<StoredPrice>, StatusCode> {
+    let currency = query.currency.unwrap_or(Currency::Usd);
+    match state.db.latest_price_with_age(currency).await {
+        Ok(Some((price, age))) => Ok(axum::Json(StoredPrice {
+            price,
+            age_secs: age.as_secs(),
+        })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::warn!("Failed to read latest stored price: {e}.");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Prometheus metrics.
+#[cfg(feature = "metrics")]
+async fn metrics() -> impl IntoResponse {
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        metrics::render(),
+    )
+}
+
+/// Health check. Responds with 503 if no price was queried successfully for too long.
+async fn health(State(state): State<AppState>) -> (StatusCode, String) {
+    if state.last_success.failing_for() <= state.health_max_age {
+        return (StatusCode::OK, "ok".to_string());
+    }
+
+    let message = match state.last_success.age() {
+        Some(age) => format!("degraded: last price queried {}s ago", age.as_secs()),
+        None => "degraded: no price queried yet".to_string(),
     };
+    (StatusCode::SERVICE_UNAVAILABLE, message)
+}
 
-    if last_timestamp.unwrap_or_default() < price.bitcoin.last_updated_at {
-        let _ = tx.send(price);
-        if let Err(e) = db.push_price(price).await {
-            anyhow::bail!("Failed to update price: {e}.");
-        };
-        *last_timestamp = Some(price.bitcoin.last_updated_at);
+/// Query the price immediately instead of waiting for the next scheduled query.
+async fn refresh_price(State(state): State<AppState>) -> StatusCode {
+    state.refresh.notify_one();
+    StatusCode::ACCEPTED
+}
+
+/// Current settings and state of the daemon.
+async fn debug(State(state): State<AppState>) -> axum::Json<Snapshot> {
+    axum::Json(Snapshot {
+        settings: state.settings.clone(),
+        last_success_age_secs: state.last_success.age().map(|age| age.as_secs()),
+        latest_price_at: state
+            .latest_rx
+            .borrow()
+            .as_ref()
+            .map(|price| price.bitcoin.last_updated_at),
+        uptime_secs: state.last_success.uptime().as_secs(),
+    })
+}
+
+/// Maximum number of prices returned by a single history request.
+const MAX_PRICES_LIMIT: u32 = 10_000;
+
+/// Prices history request.
+#[derive(Deserialize)]
+struct PricesQuery {
+    /// First unix timestamp of the range, inclusive.
+    from: u64,
+
+    /// Last unix timestamp of the range, inclusive.
+    to: u64,
+
+    /// Maximum number of prices to return.
+    limit: Option<u32>,
+
+    /// Average prices to return at most this many points, keeping the first and the last one.
+    max_points: Option<u32>,
+
+    /// Order of returned prices, by time by default.
+    #[serde(default)]
+    sort: PriceOrder,
+}
+
+/// Order of returned prices.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PriceOrder {
+    /// Oldest first.
+    #[default]
+    Time,
+
+    /// Cheapest first, then oldest first.
+    Value,
+}
+
+/// Prices stored in a time range.
+async fn prices(
+    State(state): State<AppState>,
+    Query(query): Query<PricesQuery>,
+) -> Result<axum::Json<Vec<Price>>, StatusCode> {
+    let limit = query
+        .limit
+        .unwrap_or(MAX_PRICES_LIMIT)
+        .min(MAX_PRICES_LIMIT);
+    let prices = match query.max_points {
+        Some(max_points) if max_points < 2 => return Err(StatusCode::BAD_REQUEST),
+        Some(max_points) => {
+            let max_points = max_points.min(limit);
+            state.db.downsampled(query.from, query.to, max_points).await
+        }
+        None => state.db.prices_between(query.from, query.to, limit).await,
+    };
+    match prices {
+        Ok(mut prices) => {
+            if let PriceOrder::Value = query.sort {
+                prices.sort_by(|a, b| Price::by_value(a, b).then_with(|| Price::by_time(a, b)));
+            }
+            Ok(axum::Json(prices))
+        }
+        Err(e) => {
+            tracing::warn!("Failed to read prices history: {e}.");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
+}
 
-    Ok(())
+/// Candles request.
+#[derive(Deserialize)]
+struct CandlesQuery {
+    /// First unix timestamp of the range, inclusive.
+    from: u64,
+
+    /// Last unix timestamp of the range, inclusive.
+    to: u64,
+
+    /// Bucket length in seconds.
+    bucket: u64,
+
+    /// Currency of prices, USD by default.
+    currency: Option<Currency>,
+
+    /// Fill buckets without prices with the previous close.
+    #[serde(default)]
+    fill: bool,
+}
+
+/// OHLC candles of prices stored in a time range.
+async fn candles(
+    State(state): State<AppState>,
+    Query(query): Query<CandlesQuery>,
+) -> Result<axum::Json<Vec<db::Candle>>, StatusCode> {
+    if query.bucket == 0
+        || query.to.saturating_sub(query.from) / query.bucket >= u64::from(MAX_PRICES_LIMIT)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let currency = query.currency.unwrap_or(Currency::Usd);
+    let bucket = Duration::from_secs(query.bucket);
+    match state
+        .db
+        .candles(currency, query.from, query.to, bucket, query.fill)
+        .await
+    {
+        Ok(candles) => Ok(axum::Json(candles)),
+        Err(e) => {
+            tracing::warn!("Failed to read price candles: {e}.");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Moving average request.
+#[derive(Deserialize)]
+struct MovingAverageQuery {
+    /// Number of latest prices to use.
+    window: u32,
+}
+
+/// Moving average of latest USD prices.
+#[derive(Serialize)]
+struct MovingAverage {
+    window: u32,
+
+    /// Average, if at least `window` prices are stored.
+    usd: Option<f64>,
+}
+
+/// Mean of the latest stored USD prices.
+async fn moving_average(
+    State(state): State<AppState>,
+    Query(query): Query<MovingAverageQuery>,
+) -> Result<axum::Json<MovingAverage>, StatusCode> {
+    if query.window == 0 || query.window > MAX_PRICES_LIMIT {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match state.db.moving_average(query.window).await {
+        Ok(usd) => Ok(axum::Json(MovingAverage {
+            window: query.window,
+            usd,
+        })),
+        Err(e) => {
+            tracing::warn!("Failed to compute moving average: {e}.");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Volatility of latest USD prices.
+#[derive(Serialize)]
+struct Volatility {
+    window: u32,
+
+    /// Standard deviation of log returns, if at least `window` prices are stored.
+    usd: Option<f64>,
+}
+
+/// Volatility of the latest stored USD prices.
+async fn volatility(
+    State(state): State<AppState>,
+    Query(query): Query<MovingAverageQuery>,
+) -> Result<axum::Json<Volatility>, StatusCode> {
+    if query.window < 3 || query.window > MAX_PRICES_LIMIT {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match state.db.volatility(query.window).await {
+        Ok(usd) => Ok(axum::Json(Volatility {
+            window: query.window,
+            usd,
+        })),
+        Err(e) => {
+            tracing::warn!("Failed to compute volatility: {e}.");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Maximum weight of a price in time weighted averages by default.
+const DEFAULT_TWAP_MAX_GAP: Duration = Duration::from_secs(3600);
+
+/// Time weighted average price request.
+#[derive(Deserialize)]
+struct TwapQuery {
+    /// First unix timestamp of the range, inclusive.
+    from: u64,
+
+    /// Last unix timestamp of the range, inclusive.
+    to: u64,
+
+    /// Maximum seconds a price is weighted by, one hour by default.
+    max_gap: Option<u64>,
+}
+
+/// Time weighted average of USD prices.
+#[derive(Serialize)]
+struct Twap {
+    /// Average, if any prices are stored in the range.
+    usd: Option<f64>,
+}
+
+/// Time weighted average of stored USD prices in a time range.
+async fn twap(
+    State(state): State<AppState>,
+    Query(query): Query<TwapQuery>,
+) -> Result<axum::Json<Twap>, StatusCode> {
+    let max_gap = query
+        .max_gap
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TWAP_MAX_GAP);
+    if query.from > query.to || max_gap.is_zero() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match state.db.twap(query.from, query.to, max_gap).await {
+        Ok(usd) => Ok(axum::Json(Twap { usd })),
+        Err(e) => {
+            tracing::warn!("Failed to compute time weighted average price: {e}.");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Time range request.
+#[derive(Deserialize)]
+struct RangeQuery {
+    /// First unix timestamp of the range, inclusive.
+    from: u64,
+
+    /// Last unix timestamp of the range, inclusive.
+    to: u64,
+}
+
+/// Share of successful price queries.
+#[derive(Serialize)]
+struct SuccessRate {
+    /// Successful queries divided by all queries, if any were made in the range.
+    success_rate: Option<f64>,
+}
+
+/// Share of successful price queries in a time range, as recorded with `store_errors`.
+async fn success_rate(
+    State(state): State<AppState>,
+    Query(query): Query<RangeQuery>,
+) -> Result<axum::Json<SuccessRate>, StatusCode> {
+    if query.from > query.to {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match state.db.success_rate(query.from, query.to).await {
+        Ok(success_rate) => Ok(axum::Json(SuccessRate { success_rate })),
+        Err(e) => {
+            tracing::warn!("Failed to compute query success rate: {e}.");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Lowest and highest stored USD prices in a time range.
+async fn price_extremes(
+    State(state): State<AppState>,
+    Query(query): Query<RangeQuery>,
+) -> Result<axum::Json<Option<Extremes>>, StatusCode> {
+    if query.from > query.to {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match state.db.price_extremes(query.from, query.to).await {
+        Ok(extremes) => Ok(axum::Json(extremes)),
+        Err(e) => {
+            tracing::warn!("Failed to read price extremes: {e}.");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Coverage gaps request.
+#[derive(Deserialize)]
+struct GapsQuery {
+    /// First unix timestamp of the range, inclusive.
+    from: u64,
+
+    /// Last unix timestamp of the range, inclusive.
+    to: u64,
+
+    /// Expected seconds between stored prices.
+    interval: u64,
+}
+
+/// Period without stored prices.
+#[derive(Serialize)]
+struct Gap {
+    /// Unix timestamp a price was last seen before the gap.
+    from: u64,
+
+    /// Unix timestamp of the first price after the gap.
+    to: u64,
+}
+
+/// Gaps between stored prices longer than the expected interval, e.g. during downtime.
+async fn coverage_gaps(
+    State(state): State<AppState>,
+    Query(query): Query<GapsQuery>,
+) -> Result<axum::Json<Vec<Gap>>, StatusCode> {
+    if query.interval == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let interval = Duration::from_secs(query.interval);
+    let gaps = state
+        .db
+        .coverage_gaps(query.from, query.to, interval, MAX_PRICES_LIMIT)
+        .await;
+    match gaps {
+        Ok(gaps) => Ok(axum::Json(
+            gaps.into_iter()
+                .map(|(from, to)| Gap { from, to })
+                .collect(),
+        )),
+        Err(e) => {
+            tracing::warn!("Failed to find coverage gaps: {e}.");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// How many failed queries to return by default.
+const DEFAULT_ERRORS_LIMIT: u32 = 100;
+
+/// Recent failed queries request.
+#[derive(Deserialize)]
+struct ErrorsQuery {
+    /// Maximum number of failures to return.
+    limit: Option<u32>,
+}
+
+/// Most recent stored failed price queries, newest first.
+async fn recent_errors(
+    State(state): State<AppState>,
+    Query(query): Query<ErrorsQuery>,
+) -> Result<axum::Json<Vec<QueryFailure>>, StatusCode> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_ERRORS_LIMIT)
+        .min(MAX_PRICES_LIMIT);
+    match state.db.recent_errors(limit).await {
+        Ok(errors) => Ok(axum::Json(errors)),
+        Err(e) => {
+            tracing::warn!("Failed to read price query failures: {e}.");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
 /// Upgrade HTTP connection into WebSocket.
@@ -127,7 +1308,7 @@ async fn price_ws(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl I
 }
 
 async fn handle_socket(mut socket: WebSocket, mut state: AppState) -> anyhow::Result<()> {
-    let timestamp = dbg!(Utc::now().timestamp_millis() / 1000) - 600;
+    let timestamp = Utc::now().timestamp_millis() / 1000 - 600;
     let prices = match state.db.prices_since(timestamp).await {
         Ok(p) => p,
         Err(e) => {
@@ -136,55 +1317,373 @@ async fn handle_socket(mut socket: WebSocket, mut state: AppState) -> anyhow::Re
         }
     };
 
-    for price in dbg!(prices) {
-        let json_price = Json::from(price).encode_to_string()?;
-        socket.send(Message::Text(json_price.into())).await?;
+    tracing::debug!("Sending {} history prices since {timestamp}.", prices.len());
+    if prices.is_empty() {
+        // Don't leave a fresh client blank until the next update.
+        let latest = state.latest_rx.borrow().clone();
+        if let Some(price) = latest {
+            send_price(&mut socket, price).await?;
+        }
+    }
+    for price in prices {
+        send_price(&mut socket, price).await?;
     }
 
-    while let Ok(price) = state.prices_rx.recv().await {
-        let json_price = Json::from(price).encode_to_string()?;
-        socket.send(Message::Text(json_price.into())).await?;
+    loop {
+        match state.prices_rx.recv().await {
+            Ok(price) => send_price(&mut socket, price).await?,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("WS client lagged behind, {skipped} prices skipped.");
+            }
+            Err(RecvError::Closed) => break,
+        }
     }
 
     Ok(())
 }
 
+/// How long a WebSocket client may take to accept a price before it is disconnected.
+const WS_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Send price to a WebSocket client, failing if the client is too slow.
+async fn send_price(socket: &mut WebSocket, price: Price) -> anyhow::Result<()> {
+    let message = Message::Text(price.to_json().into());
+    tokio::time::timeout(WS_SEND_TIMEOUT, socket.send(message))
+        .await
+        .map_err(|_| anyhow::Error::msg("WS client is too slow"))??;
+    Ok(())
+}
+
 /// Price data.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Price {
     /// BTC info.
     pub bitcoin: PriceInfo,
+
+    /// Name of the source that produced the price. Not known for stored prices.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub source: Option<&'static str>,
+
+    /// Change since the previously queried price. Not known for stored prices.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub change: Option<PriceChange>,
+
+    /// Agreement of the sources of an aggregated price. Only known for median prices.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<Confidence>,
+
+    /// Upstream API response the price was parsed from. Only known for CoinGecko prices.
+    #[serde(skip)]
+    pub raw: Option<Arc<serde_json::Value>>,
+}
+
+impl Price {
+    /// Serialize into CoinGecko-like JSON: `{"bitcoin":{"usd":63214.57,"last_updated_at":1700000000}}`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self).expect("price is always serializable")
+    }
+
+    /// Check that prices are finite and positive and the update time is plausible.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        for currency in Currency::ALL {
+            if let Some(value) = self.bitcoin.price(currency)
+                && !(value.is_finite() && value > 0.0)
+            {
+                return Err(ValidationError::InvalidValue {
+                    currency: currency.code(),
+                    value,
+                });
+            }
+        }
+
+        let timestamp = self.bitcoin.last_updated_at;
+        if timestamp == 0 {
+            return Err(ValidationError::ZeroTimestamp);
+        }
+        let max_timestamp = Utc::now().timestamp() as u64 + MAX_CLOCK_SKEW.as_secs();
+        if timestamp > max_timestamp {
+            return Err(ValidationError::FutureTimestamp(timestamp));
+        }
+
+        Ok(())
+    }
+
+    /// Order by update time, then by USD price like [`Price::by_value`].
+    pub fn by_time(a: &Price, b: &Price) -> std::cmp::Ordering {
+        a.bitcoin
+            .last_updated_at
+            .cmp(&b.bitcoin.last_updated_at)
+            .then_with(|| Price::by_value(a, b))
+    }
+
+    /// Order by USD price. NaN prices are ordered deterministically, after all other prices
+    /// unless negative.
+    pub fn by_value(a: &Price, b: &Price) -> std::cmp::Ordering {
+        a.bitcoin.usd.total_cmp(&b.bitcoin.usd)
+    }
 }
 
-/// USD value.
+impl std::fmt::Display for Price {
+    /// Summary like `BTC $63,214.57 @ 2024-01-01T00:00:00Z`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cents = self.bitcoin.usd_cents();
+        let sign = if cents < 0 { "-" } else { "" };
+        let dollars = (cents.unsigned_abs() / 100).to_string();
+        let mut grouped = String::new();
+        for (i, digit) in dollars.chars().enumerate() {
+            if i > 0 && (dollars.len() - i).is_multiple_of(3) {
+                grouped.push(',');
+            }
+            grouped.push(digit);
+        }
+
+        write!(f, "BTC {sign}${grouped}.{:02}", cents.unsigned_abs() % 100)?;
+        match DateTime::from_timestamp(self.bitcoin.last_updated_at as i64, 0) {
+            Some(at) => write!(f, " @ {}", at.format("%Y-%m-%dT%H:%M:%SZ")),
+            None => write!(f, " @ {}", self.bitcoin.last_updated_at),
+        }
+    }
+}
+
+/// How far in the future price update times may be, allowing for clock differences.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+/// USD price change between two prices.
+#[derive(Debug, Clone, Copy, Serialize)]
+struct PriceChange {
+    /// Price difference.
+    pub absolute: f64,
+
+    /// Price difference relative to the previous price, in percents. Zero if the previous price is zero.
+    pub percent: f64,
+
+    /// Update timestamp of the previous price.
+    pub from_ts: u64,
+
+    /// Update timestamp of the current price.
+    pub to_ts: u64,
+}
+
+/// USD price change from `prev` to `curr`.
+fn compute_change(prev: &Price, curr: &Price) -> PriceChange {
+    let absolute = curr.bitcoin.usd - prev.bitcoin.usd;
+    let percent = if prev.bitcoin.usd == 0.0 {
+        0.0
+    } else {
+        absolute / prev.bitcoin.usd * 100.0
+    };
+
+    PriceChange {
+        absolute,
+        percent,
+        from_ts: prev.bitcoin.last_updated_at,
+        to_ts: curr.bitcoin.last_updated_at,
+    }
+}
+
+/// BTC value in supported currencies.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 struct PriceInfo {
     /// Price value.
-    pub usd: u64,
+    #[serde(deserialize_with = "de::number_or_string")]
+    pub usd: f64,
+
+    /// Price value in EUR, if queried.
+    #[serde(
+        default,
+        deserialize_with = "de::opt_number_or_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub eur: Option<f64>,
+
+    /// Price value in GBP, if queried.
+    #[serde(
+        default,
+        deserialize_with = "de::opt_number_or_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gbp: Option<f64>,
+
+    /// Market capitalization in USD, if reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usd_market_cap: Option<f64>,
+
+    /// Trading volume in USD over the last 24 hours, if reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usd_24h_vol: Option<f64>,
+
+    /// USD price change over the last 24 hours in percent, if reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usd_24h_change: Option<f64>,
 
     /// Last update unix timestamp.
     pub last_updated_at: u64,
 }
 
-/// Query latest BTC price in USD.
-async fn query_price() -> Result<Price, anyhow::Error> {
-    let params = [
-        ("vs_currencies", "usd"),
-        ("ids", "bitcoin"),
-        ("include_last_updated_at", "true"),
-    ];
-    let client = reqwest::Client::new();
-    let response = client
-        .get("https://api.coingecko.com/api/v3/simple/price")
-        .query(&params)
-        .send()
-        .await?;
+impl PriceInfo {
+    /// USD price in whole cents, rounding half to even.
+    pub fn usd_cents(&self) -> i64 {
+        (self.usd * 100.0).round_ties_even() as i64
+    }
 
-    if !response.status().is_success() {
-        tracing::debug!("Failed to query price info: {response:?}.");
+    /// Price value in `currency`, if known.
+    pub fn price(&self, currency: Currency) -> Option<f64> {
+        match currency {
+            Currency::Usd => Some(self.usd),
+            Currency::Eur => self.eur,
+            Currency::Gbp => self.gbp,
+        }
+    }
+}
+
+/// Supported price currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+enum Currency {
+    Usd,
+    Eur,
+    Gbp,
+}
+
+impl Currency {
+    /// All supported currencies.
+    pub const ALL: [Self; 3] = [Self::Usd, Self::Eur, Self::Gbp];
+
+    /// Currency code, as used by the price API.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Usd => "usd",
+            Self::Eur => "eur",
+            Self::Gbp => "gbp",
+        }
+    }
+}
+
+impl FromStr for Currency {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        normalize_currency(s)
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = QueryError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        normalize_currency(&s)
     }
+}
+
+/// Parse a currency code case-insensitively, ignoring surrounding whitespace.
+///
+/// Fails with [`QueryError::UnsupportedCurrency`] listing supported currencies if the code is
+/// not supported.
+fn normalize_currency(input: &str) -> Result<Currency, QueryError> {
+    let code = input.trim().to_lowercase();
+    Currency::ALL
+        .into_iter()
+        .find(|currency| currency.code() == code)
+        .ok_or_else(|| QueryError::UnsupportedCurrency(input.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::source::tests::{StubSource, price};
+    use crate::store::tests::VecStore;
 
-    tracing::debug!("{response:?}");
+    use super::*;
 
-    Ok(response.json().await?)
+    fn jump_policy() -> PricePolicy {
+        PricePolicy {
+            max_jump_percent: Some(10.0),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn small_move_is_not_confirmed() {
+        let source = StubSource::new([Ok(price(105.0, 2))]);
+
+        let checked = fetch_checked(&source, Some(&price(100.0, 1)), &jump_policy()).await;
+
+        assert_eq!(checked.unwrap().bitcoin.usd, 105.0);
+        assert_eq!(source.fetches(), 1);
+    }
+
+    #[tokio::test]
+    async fn confirmed_jump_is_accepted() {
+        let source = StubSource::new([Ok(price(150.0, 2)), Ok(price(151.0, 3))]);
+
+        let checked = fetch_checked(&source, Some(&price(100.0, 1)), &jump_policy()).await;
+
+        assert_eq!(checked.unwrap().bitcoin.usd, 151.0);
+        assert_eq!(source.fetches(), 2);
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_jump_is_rejected() {
+        let source = StubSource::new([Ok(price(150.0, 2)), Ok(price(100.0, 3))]);
+
+        let checked = fetch_checked(&source, Some(&price(100.0, 1)), &jump_policy()).await;
+
+        let error = checked.unwrap_err().to_string();
+        assert!(error.contains("Unconfirmed price jump"), "{error}");
+        assert_eq!(source.fetches(), 2);
+    }
+
+    #[test]
+    fn currency_code_is_normalized() {
+        assert_eq!(normalize_currency("eur").unwrap(), Currency::Eur);
+        assert_eq!(normalize_currency(" GBP ").unwrap(), Currency::Gbp);
+        assert_eq!("Usd".parse::<Currency>().unwrap(), Currency::Usd);
+    }
+
+    #[test]
+    fn unsupported_currency_fails() {
+        let error = normalize_currency("JPY").unwrap_err();
+        assert!(
+            matches!(&error, QueryError::UnsupportedCurrency(code) if code == "JPY"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn scraped_price_is_stored() {
+        let source = StubSource::new([Ok(price(100.0, 1))]);
+        let store = VecStore::default();
+
+        let stored = scrape_and_store(&source, &store, None).await.unwrap();
+
+        assert_eq!(stored.price.bitcoin.usd, 100.0);
+        assert!(!stored.deduplicated);
+        assert_eq!(store.usd_prices(), [100.0]);
+    }
+
+    #[tokio::test]
+    async fn unchanged_price_is_deduplicated() {
+        let source = StubSource::new([Ok(price(100.0, 1)), Ok(price(100.004, 2))]);
+        let store = VecStore::default();
+        scrape_and_store(&source, &store, Some(0.0)).await.unwrap();
+
+        let stored = scrape_and_store(&source, &store, Some(0.0)).await.unwrap();
+
+        assert!(stored.deduplicated);
+        assert_eq!(stored.price.bitcoin.last_updated_at, 1);
+        assert_eq!(store.usd_prices(), [100.0]);
+        assert_eq!(*store.touches.lock().unwrap(), [(1, 2)]);
+    }
+
+    #[tokio::test]
+    async fn invalid_price_is_not_stored() {
+        let source = StubSource::new([Ok(price(100.0, 0))]);
+        let store = VecStore::default();
+
+        let Err(error) = scrape_and_store(&source, &store, None).await else {
+            panic!("invalid price was stored");
+        };
+
+        assert!(matches!(error, StoreError::Invalid(_)), "{error}");
+        assert!(store.usd_prices().is_empty());
+    }
 }
@@ -0,0 +1,242 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use sqlx::types::chrono::NaiveDate;
+
+use crate::Price;
+use crate::error::QueryError;
+use crate::source::PriceSource;
+
+static QUERIES: AtomicU64 = AtomicU64::new(0);
+static SUCCESSES: AtomicU64 = AtomicU64::new(0);
+static FAILURES: AtomicU64 = AtomicU64::new(0);
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+
+/// Upper bounds of price query duration histogram buckets, in seconds.
+const DURATION_BUCKETS: [f64; 5] = [0.1, 0.25, 0.5, 1.0, 5.0];
+
+/// Durations of queries that weren't retried and of retried ones.
+static DURATIONS: [Histogram; 2] = [Histogram::new(), Histogram::new()];
+
+/// Histogram of price query durations.
+struct Histogram {
+    /// Queries per duration bucket, the last one counting slower queries.
+    buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; DURATION_BUCKETS.len() + 1],
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|&le| elapsed.as_secs_f64() <= le)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Render samples of histogram `name` with `labels` in Prometheus text format.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut count = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let le = DURATION_BUCKETS
+                .get(i)
+                .map_or("+Inf".to_string(), |le| le.to_string());
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{le}\"}} {count}");
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {sum}");
+        let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
+    }
+}
+
+/// Bits of the last queried USD price.
+static LAST_PRICE: AtomicU64 = AtomicU64::new(0);
+static LAST_SUCCESS_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+
+/// Source recording metrics of queries of the wrapped source.
+pub struct MeteredPriceSource<S>(pub S);
+
+impl<S: PriceSource> MeteredPriceSource<S> {
+    async fn fetch_metered(&self) -> (Result<Price, QueryError>, u32) {
+        let started = Instant::now();
+        let (result, retries) = self.0.fetch_retried().await;
+        record_query(&result, started.elapsed(), retries > 0);
+        (result, retries)
+    }
+}
+
+impl<S: PriceSource> PriceSource for MeteredPriceSource<S> {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+        Box::pin(async { self.fetch_metered().await.0 })
+    }
+
+    fn fetch_retried(&self) -> BoxFuture<'_, (Result<Price, QueryError>, u32)> {
+        Box::pin(self.fetch_metered())
+    }
+
+    fn fetch_at(&self, date: NaiveDate) -> BoxFuture<'_, Result<Price, QueryError>> {
+        self.0.fetch_at(date)
+    }
+}
+
+/// Record outcome of a price query that took `elapsed`, including retries if it was `retried`.
+fn record_query(result: &Result<Price, QueryError>, elapsed: Duration, retried: bool) {
+    QUERIES.fetch_add(1, Ordering::Relaxed);
+    DURATIONS[usize::from(retried)].record(elapsed);
+    match result {
+        Ok(price) => {
+            SUCCESSES.fetch_add(1, Ordering::Relaxed);
+            LAST_PRICE.store(price.bitcoin.usd.to_bits(), Ordering::Relaxed);
+            LAST_SUCCESS_TIMESTAMP.store(price.bitcoin.last_updated_at, Ordering::Relaxed);
+        }
+        Err(_) => {
+            FAILURES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Record a retried request to a price API.
+pub fn record_retry() {
+    RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Render metrics in Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        let _ = writeln!(out, "{name} {value}");
+    };
+
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed) as f64;
+    metric(
+        "btc_scrapper_price_queries_total",
+        "counter",
+        "Price update queries.",
+        load(&QUERIES),
+    );
+    metric(
+        "btc_scrapper_price_query_successes_total",
+        "counter",
+        "Successful price update queries.",
+        load(&SUCCESSES),
+    );
+    metric(
+        "btc_scrapper_price_query_failures_total",
+        "counter",
+        "Failed price update queries.",
+        load(&FAILURES),
+    );
+    metric(
+        "btc_scrapper_price_api_retries_total",
+        "counter",
+        "Retried price API requests.",
+        load(&RETRIES),
+    );
+    metric(
+        "btc_scrapper_last_price_usd",
+        "gauge",
+        "Last queried BTC price in USD.",
+        f64::from_bits(LAST_PRICE.load(Ordering::Relaxed)),
+    );
+    metric(
+        "btc_scrapper_last_success_timestamp_seconds",
+        "gauge",
+        "Update time of the last queried price.",
+        load(&LAST_SUCCESS_TIMESTAMP),
+    );
+
+    let name = "btc_scrapper_price_query_duration_seconds";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Duration of price update queries, including retries, by whether any \
+         request was retried."
+    );
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    DURATIONS[0].render(&mut out, name, "retried=\"false\"");
+    DURATIONS[1].render(&mut out, name, "retried=\"true\"");
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::tests::price;
+
+    /// Source of a price queried with `.0` retried requests.
+    struct RetriedSource(u32);
+
+    impl PriceSource for RetriedSource {
+        fn name(&self) -> &'static str {
+            "retried"
+        }
+
+        fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+            Box::pin(async { Ok(price(100.0, 1)) })
+        }
+
+        fn fetch_retried(&self) -> BoxFuture<'_, (Result<Price, QueryError>, u32)> {
+            Box::pin(async { (Ok(price(100.0, 1)), self.0) })
+        }
+    }
+
+    /// Number of durations recorded by `histogram`.
+    fn count(histogram: &Histogram) -> u64 {
+        histogram
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    #[test]
+    fn histogram_renders_cumulative_buckets() {
+        let histogram = Histogram::new();
+        histogram.record(Duration::from_millis(200));
+
+        let mut rendered = String::new();
+        histogram.render(&mut rendered, "duration", "retried=\"true\"");
+        assert!(
+            rendered.contains("duration_bucket{retried=\"true\",le=\"0.1\"} 0\n"),
+            "{rendered}"
+        );
+        assert!(
+            rendered.contains("duration_bucket{retried=\"true\",le=\"0.25\"} 1\n"),
+            "{rendered}"
+        );
+        assert!(
+            rendered.contains("duration_count{retried=\"true\"} 1\n"),
+            "{rendered}"
+        );
+    }
+
+    #[tokio::test]
+    async fn durations_are_labeled_by_retries() {
+        let (not_retried, retried) = (count(&DURATIONS[0]), count(&DURATIONS[1]));
+
+        MeteredPriceSource(RetriedSource(1)).fetch().await.unwrap();
+        assert_eq!(count(&DURATIONS[1]), retried + 1);
+        assert_eq!(count(&DURATIONS[0]), not_retried);
+
+        MeteredPriceSource(RetriedSource(0)).fetch().await.unwrap();
+        assert_eq!(count(&DURATIONS[0]), not_retried + 1);
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Enforces a minimum interval between requests.
+pub struct RateLimiter {
+    min_interval: Duration,
+    last_request_at: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    /// Limiter letting a request through at most once per `min_interval`.
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last_request_at: Mutex::new(None),
+        }
+    }
+
+    /// Wait until a request may be sent. Concurrent callers are let through one at a time.
+    pub async fn wait(&self) {
+        let mut last_request_at = self.last_request_at.lock().await;
+        if let Some(last) = *last_request_at {
+            tokio::time::sleep_until(last + self.min_interval).await;
+        }
+        *last_request_at = Some(Instant::now());
+    }
+}
+
+/// Token bucket limiting how many retries may be made per minute.
+pub struct RetryBudget {
+    per_minute: u32,
+    state: StdMutex<Bucket>,
+}
+
+/// Retries left and when they were last counted.
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RetryBudget {
+    /// Full budget of `per_minute` retries, refilling continuously at the same rate.
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            state: StdMutex::new(Bucket {
+                tokens: per_minute as f64,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take one retry from the budget. Returns `false` if the budget is exhausted.
+    pub fn try_take(&self) -> bool {
+        let mut bucket = self.state.lock().expect("retry budget is not poisoned");
+        let now = Instant::now();
+        let refill = (now - bucket.refilled_at).as_secs_f64() * self.per_minute as f64 / 60.0;
+        bucket.tokens = (bucket.tokens + refill).min(self.per_minute as f64);
+        bucket.refilled_at = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, SET_COOKIE};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+/// Whether to record price API responses or replay recorded ones.
+#[derive(Debug, Clone)]
+pub enum Recording {
+    /// Append responses to a JSON lines file.
+    Record(PathBuf),
+
+    /// Serve responses from a file written in record mode instead of sending requests.
+    Replay(PathBuf),
+}
+
+/// Recorded response to a request.
+#[derive(Serialize, Deserialize)]
+struct Exchange {
+    /// Requested URL, without password and query, which may hold API keys.
+    url: String,
+    status: u16,
+
+    /// Response headers, with cookie values redacted.
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+/// Appends responses to a recording file.
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Recorder appending to `path`, creating it if needed.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open recording {}", path.display()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Record a response to a request of `url`, without its query, logging failures.
+    pub fn record(&self, url: &Url, status: StatusCode, headers: &HeaderMap, body: &str) {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| {
+                let value = value.to_str().unwrap_or_default();
+                if name == SET_COOKIE {
+                    let cookie = value.split_once('=').map_or("?", |(name, _)| name);
+                    return (name.to_string(), format!("{cookie}=<redacted>"));
+                }
+                (name.to_string(), value.to_string())
+            })
+            .collect();
+        let exchange = Exchange {
+            url: replay_key(url),
+            status: status.as_u16(),
+            headers,
+            body: body.to_string(),
+        };
+        let mut line = serde_json::to_string(&exchange).expect("exchange is always serializable");
+        line.push('\n');
+
+        let mut file = self.file.lock().expect("recording file is not poisoned");
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            tracing::warn!("Failed to record response: {e}.");
+        }
+    }
+}
+
+/// Serves recorded responses.
+pub struct Replayer {
+    /// Responses by URL without query, with the index of the next one to serve.
+    exchanges: Mutex<HashMap<String, (Vec<Exchange>, usize)>>,
+}
+
+impl Replayer {
+    /// Replayer of responses recorded to `path`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open recording {}", path.display()))?;
+        let mut exchanges: HashMap<_, (Vec<_>, _)> = HashMap::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let invalid = || format!("Invalid recording line {}", number + 1);
+            let exchange: Exchange = serde_json::from_str(&line?).with_context(invalid)?;
+            let url: Url = exchange.url.parse().with_context(invalid)?;
+            exchanges
+                .entry(replay_key(&url))
+                .or_default()
+                .0
+                .push(exchange);
+        }
+        Ok(Self {
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+
+    /// Next response recorded for a request of `url`, ignoring its query, repeating the last one
+    /// once all are served. Responds with 404 if no response to `url` is recorded.
+    pub fn response(&self, url: &Url) -> (StatusCode, HeaderMap, String) {
+        let mut exchanges = self.exchanges.lock().expect("recording is not poisoned");
+        let Some((recorded, next)) = exchanges.get_mut(&replay_key(url)) else {
+            tracing::warn!("No response to {url} is recorded.");
+            return (StatusCode::NOT_FOUND, HeaderMap::new(), String::new());
+        };
+        let exchange = &recorded[(*next).min(recorded.len() - 1)];
+        *next += 1;
+
+        let headers = exchange
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    HeaderName::try_from(name).ok()?,
+                    HeaderValue::try_from(value).ok()?,
+                ))
+            })
+            .collect();
+        let status = StatusCode::from_u16(exchange.status).unwrap_or(StatusCode::NOT_FOUND);
+        (status, headers, exchange.body.clone())
+    }
+}
+
+/// `url` without query, matching requests to recorded responses.
+fn replay_key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_query(None);
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_url_has_no_query() {
+        let path = std::env::temp_dir().join(format!("recording-{}.jsonl", std::process::id()));
+        let url: Url = "https://api.example.com/price?ids=bitcoin&x_cg_pro_api_key=secret"
+            .parse()
+            .unwrap();
+        Recorder::open(&path)
+            .unwrap()
+            .record(&url, StatusCode::OK, &HeaderMap::new(), "{}");
+
+        let recorded = std::fs::read_to_string(&path).unwrap();
+        let replayed = Replayer::load(&path).unwrap().response(&url);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!recorded.contains("secret"), "{recorded}");
+        assert!(
+            recorded.contains(r#""url":"https://api.example.com/price""#),
+            "{recorded}"
+        );
+        assert_eq!(replayed.0, StatusCode::OK);
+        assert_eq!(replayed.2, "{}");
+    }
+}
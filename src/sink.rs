@@ -0,0 +1,68 @@
+use std::io::Write;
+
+use futures_util::future::BoxFuture;
+
+use crate::Price;
+use crate::store::PriceStore;
+
+/// Destination of queried prices.
+pub trait PriceSink: Send + Sync {
+    /// Sink name used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Deliver `price`.
+    fn emit<'a>(&'a self, price: &'a Price) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Prints prices to stdout as JSON lines.
+pub struct StdoutSink;
+
+impl PriceSink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn emit<'a>(&'a self, price: &'a Price) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut stdout = std::io::stdout().lock();
+            writeln!(stdout, "{}", price.to_json())?;
+            stdout.flush()?;
+            Ok(())
+        })
+    }
+}
+
+/// Stores every price in a [`PriceStore`].
+pub struct StoreSink<S>(pub S);
+
+impl<S: PriceStore> PriceSink for StoreSink<S> {
+    fn name(&self) -> &'static str {
+        "db"
+    }
+
+    fn emit<'a>(&'a self, price: &'a Price) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.0.push_price(price.clone())
+    }
+}
+
+/// Discards prices.
+pub struct NoopSink;
+
+impl PriceSink for NoopSink {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    fn emit<'a>(&'a self, _price: &'a Price) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Deliver `price` to all `sinks`, logging failures of each one.
+pub async fn emit_all(sinks: &[Box<dyn PriceSink>], price: &Price) {
+    for sink in sinks {
+        if let Err(e) = sink.emit(price).await {
+            tracing::warn!("Failed to emit price to {} sink: {e}.", sink.name());
+        }
+    }
+}
@@ -0,0 +1,558 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use futures_util::Stream;
+use futures_util::future::{BoxFuture, join, join_all};
+use serde::Serialize;
+use sqlx::types::chrono::{NaiveDate, Utc};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+use crate::error::QueryError;
+use crate::store::{QueryFailure, QueryOutcome};
+use crate::{Price, PriceInfo};
+
+/// Service providing the latest BTC price.
+pub trait PriceSource: Send + Sync {
+    /// Source name, recorded in the prices it produces.
+    fn name(&self) -> &'static str;
+
+    /// Query the latest price.
+    fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>>;
+
+    /// Query the latest price like [`PriceSource::fetch`], also returning how many requests
+    /// to price APIs were retried.
+    fn fetch_retried(&self) -> BoxFuture<'_, (Result<Price, QueryError>, u32)> {
+        Box::pin(async { (self.fetch().await, 0) })
+    }
+
+    /// Query the price at the start of `date` (UTC), if the source keeps history.
+    fn fetch_at(&self, date: NaiveDate) -> BoxFuture<'_, Result<Price, QueryError>> {
+        let _ = date;
+        Box::pin(async { Err(QueryError::Unsupported) })
+    }
+}
+
+impl<T: PriceSource + ?Sized> PriceSource for Box<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+        (**self).fetch()
+    }
+
+    fn fetch_retried(&self) -> BoxFuture<'_, (Result<Price, QueryError>, u32)> {
+        (**self).fetch_retried()
+    }
+
+    fn fetch_at(&self, date: NaiveDate) -> BoxFuture<'_, Result<Price, QueryError>> {
+        (**self).fetch_at(date)
+    }
+}
+
+/// Prices fetched from `source` every `interval`, starting immediately.
+///
+/// Failed queries are yielded as errors and don't end the stream.
+pub fn price_stream<S: PriceSource>(
+    source: S,
+    interval: Duration,
+) -> impl Stream<Item = Result<Price, QueryError>> {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    futures_util::stream::unfold((source, ticker), |(source, mut ticker)| async move {
+        ticker.tick().await;
+        let result = source.fetch().await;
+        Some((result, (source, ticker)))
+    })
+}
+
+/// Time of the last successful price query. Cheap to read concurrently.
+#[derive(Debug)]
+pub struct LastSuccess {
+    started: Instant,
+
+    /// Milliseconds since `started` plus one, zero if no query succeeded yet.
+    millis: AtomicU64,
+}
+
+impl LastSuccess {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            millis: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a successful query made now.
+    pub fn record(&self) {
+        let millis = self.started.elapsed().as_millis() as u64 + 1;
+        self.millis.store(millis, Ordering::Relaxed);
+    }
+
+    /// Time since the last successful query, if any.
+    pub fn age(&self) -> Option<Duration> {
+        match self.millis.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(self.started.elapsed() - Duration::from_millis(millis - 1)),
+        }
+    }
+
+    /// Time since tracking started.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Time since the last successful query, or since tracking started if none succeeded.
+    pub fn failing_for(&self) -> Duration {
+        self.age().unwrap_or_else(|| self.uptime())
+    }
+}
+
+impl Default for LastSuccess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Source recording when `inner` last fetched a price, and optionally its failures.
+pub struct TrackedPriceSource<S> {
+    inner: S,
+    last_success: Arc<LastSuccess>,
+    outcomes: Option<mpsc::Sender<QueryOutcome>>,
+}
+
+impl<S: PriceSource> TrackedPriceSource<S> {
+    pub fn new(inner: S, last_success: Arc<LastSuccess>) -> Self {
+        Self {
+            inner,
+            last_success,
+            outcomes: None,
+        }
+    }
+
+    /// Send outcomes of queries to `outcomes`. Outcomes are dropped if the channel is full.
+    pub fn with_outcomes(mut self, outcomes: mpsc::Sender<QueryOutcome>) -> Self {
+        self.outcomes = Some(outcomes);
+        self
+    }
+
+    async fn fetch_tracked(&self) -> Result<Price, QueryError> {
+        let result = self.inner.fetch().await;
+        if result.is_ok() {
+            self.last_success.record();
+        }
+        if let Some(outcomes) = &self.outcomes {
+            let outcome = match &result {
+                Ok(_) => QueryOutcome::Success(Utc::now().timestamp() as u64),
+                Err(e) => QueryOutcome::Failure(QueryFailure::new(e)),
+            };
+            if outcomes.try_send(outcome).is_err() {
+                tracing::debug!("Dropped query outcome record.");
+            }
+        }
+        result
+    }
+}
+
+impl<S: PriceSource> PriceSource for TrackedPriceSource<S> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+        Box::pin(self.fetch_tracked())
+    }
+
+    fn fetch_at(&self, date: NaiveDate) -> BoxFuture<'_, Result<Price, QueryError>> {
+        self.inner.fetch_at(date)
+    }
+}
+
+/// Source trying several sources in order and returning the first price fetched.
+pub struct CompositePriceSource {
+    sources: Vec<Box<dyn PriceSource>>,
+}
+
+impl CompositePriceSource {
+    /// Composite source starting with `primary`.
+    pub fn new(primary: Box<dyn PriceSource>) -> Self {
+        Self {
+            sources: vec![primary],
+        }
+    }
+
+    /// Try `source` if all previously added sources fail.
+    pub fn with_fallback(mut self, source: Box<dyn PriceSource>) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// Fetch price from sources in order, returning the last error if all of them fail,
+    /// and the number of requests retried by the sources tried.
+    async fn fetch_first(&self) -> (Result<Price, QueryError>, u32) {
+        let (last, rest) = self.sources.split_last().expect("at least one source");
+        let mut retries = 0;
+        for source in rest {
+            let (result, source_retries) = source.fetch_retried().await;
+            retries += source_retries;
+            match result {
+                Ok(price) => return (Ok(price), retries),
+                Err(e) => tracing::info!("Failed to query price from {}: {e}.", source.name()),
+            }
+        }
+        let (result, last_retries) = last.fetch_retried().await;
+        (result, retries + last_retries)
+    }
+
+    /// Fetch historical price from the first source that keeps history and succeeds.
+    async fn fetch_first_at(&self, date: NaiveDate) -> Result<Price, QueryError> {
+        let mut error = QueryError::Unsupported;
+        for source in &self.sources {
+            match source.fetch_at(date).await {
+                Ok(price) => return Ok(price),
+                Err(QueryError::Unsupported) => {}
+                Err(e) => {
+                    tracing::info!("Failed to query {date} price from {}: {e}.", source.name());
+                    error = e;
+                }
+            }
+        }
+        Err(error)
+    }
+}
+
+impl PriceSource for CompositePriceSource {
+    fn name(&self) -> &'static str {
+        "composite"
+    }
+
+    fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+        Box::pin(async { self.fetch_first().await.0 })
+    }
+
+    fn fetch_retried(&self) -> BoxFuture<'_, (Result<Price, QueryError>, u32)> {
+        Box::pin(self.fetch_first())
+    }
+
+    fn fetch_at(&self, date: NaiveDate) -> BoxFuture<'_, Result<Price, QueryError>> {
+        Box::pin(self.fetch_first_at(date))
+    }
+}
+
+/// Source querying all its sources concurrently and returning the median price.
+pub struct MedianPriceSource {
+    sources: Vec<Box<dyn PriceSource>>,
+    min_sources: usize,
+}
+
+impl MedianPriceSource {
+    /// Median of `sources`, requiring at least `min_sources` of them to succeed.
+    pub fn new(sources: Vec<Box<dyn PriceSource>>, min_sources: usize) -> Self {
+        Self {
+            sources,
+            min_sources,
+        }
+    }
+}
+
+impl PriceSource for MedianPriceSource {
+    fn name(&self) -> &'static str {
+        "median"
+    }
+
+    fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+        Box::pin(async { aggregate_median(&self.sources, self.min_sources).await.0 })
+    }
+
+    fn fetch_retried(&self) -> BoxFuture<'_, (Result<Price, QueryError>, u32)> {
+        Box::pin(aggregate_median(&self.sources, self.min_sources))
+    }
+}
+
+/// How much the sources of an aggregated price agree.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Confidence {
+    /// Sources that provided a price.
+    pub sources_used: usize,
+
+    /// Sources queried.
+    pub sources_total: usize,
+
+    /// Difference of the highest and the lowest USD price, in percent of the median.
+    pub spread_pct: f64,
+}
+
+/// Query all `sources` concurrently and combine successful results into their median price,
+/// reporting its confidence in [`Price::confidence`].
+///
+/// The result is as recent as the most recent contributing price.
+/// Fails if fewer than `min_sources` sources succeed.
+/// Also returns the number of requests retried by all sources.
+pub async fn aggregate_median(
+    sources: &[Box<dyn PriceSource>],
+    min_sources: usize,
+) -> (Result<Price, QueryError>, u32) {
+    let results = join_all(sources.iter().map(|source| source.fetch_retried())).await;
+    let retries = results.iter().map(|(_, retries)| retries).sum();
+    let results = results.into_iter().map(|(result, _)| result).collect();
+    let price =
+        median_with_confidence(sources, results, min_sources).map(|(mut price, confidence)| {
+            price.confidence = Some(confidence);
+            price
+        });
+    (price, retries)
+}
+
+/// Median price of `results` fetched from `sources` like [`aggregate_median`], and how much
+/// the sources agree.
+fn median_with_confidence(
+    sources: &[Box<dyn PriceSource>],
+    results: Vec<Result<Price, QueryError>>,
+    min_sources: usize,
+) -> Result<(Price, Confidence), QueryError> {
+    let mut prices = Vec::with_capacity(results.len());
+    for (source, result) in sources.iter().zip(results) {
+        match result {
+            Ok(price) => prices.push(price),
+            Err(e) => tracing::info!("Failed to query price from {}: {e}.", source.name()),
+        }
+    }
+
+    if prices.is_empty() || prices.len() < min_sources {
+        return Err(QueryError::NotEnoughSources {
+            succeeded: prices.len(),
+            required: min_sources.max(1),
+        });
+    }
+
+    let latest = prices
+        .iter()
+        .max_by(|a, b| Price::by_time(a, b))
+        .expect("prices are not empty")
+        .clone();
+    let prices: Vec<_> = prices.into_iter().map(|p| p.bitcoin).collect();
+    let usd = median(prices.iter().map(|p| p.usd).collect()).expect("prices are not empty");
+    let (min, max) = prices
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), p| {
+            (min.min(p.usd), max.max(p.usd))
+        });
+    let confidence = Confidence {
+        sources_used: prices.len(),
+        sources_total: sources.len(),
+        spread_pct: (max - min) / usd * 100.0,
+    };
+    let price = Price {
+        bitcoin: PriceInfo {
+            usd,
+            eur: median(prices.iter().filter_map(|p| p.eur).collect()),
+            gbp: median(prices.iter().filter_map(|p| p.gbp).collect()),
+            usd_market_cap: median(prices.iter().filter_map(|p| p.usd_market_cap).collect()),
+            usd_24h_vol: median(prices.iter().filter_map(|p| p.usd_24h_vol).collect()),
+            usd_24h_change: median(prices.iter().filter_map(|p| p.usd_24h_change).collect()),
+            last_updated_at: latest.bitcoin.last_updated_at,
+        },
+        source: Some("median"),
+        change: None,
+        confidence: None,
+        raw: None,
+    };
+    Ok((price, confidence))
+}
+
+/// Source returning the price of its primary source only if a secondary source agrees with it.
+pub struct VerifiedPriceSource {
+    primary: Box<dyn PriceSource>,
+    secondary: Box<dyn PriceSource>,
+    tolerance_percent: f64,
+}
+
+impl VerifiedPriceSource {
+    /// Price of `primary`, verified to be within `tolerance_percent` of `secondary`'s.
+    pub fn new(
+        primary: Box<dyn PriceSource>,
+        secondary: Box<dyn PriceSource>,
+        tolerance_percent: f64,
+    ) -> Self {
+        Self {
+            primary,
+            secondary,
+            tolerance_percent,
+        }
+    }
+}
+
+impl PriceSource for VerifiedPriceSource {
+    fn name(&self) -> &'static str {
+        "verified"
+    }
+
+    fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+        Box::pin(async { self.fetch_retried().await.0 })
+    }
+
+    fn fetch_retried(&self) -> BoxFuture<'_, (Result<Price, QueryError>, u32)> {
+        Box::pin(query_verified(
+            &self.primary,
+            &self.secondary,
+            self.tolerance_percent,
+        ))
+    }
+}
+
+/// Query `primary` and `secondary` concurrently and return the primary price if their USD
+/// prices differ by at most `tolerance_percent` of the primary one.
+/// Also returns the number of requests retried by both sources.
+pub async fn query_verified(
+    primary: &dyn PriceSource,
+    secondary: &dyn PriceSource,
+    tolerance_percent: f64,
+) -> (Result<Price, QueryError>, u32) {
+    let ((price, primary_retries), (check, secondary_retries)) =
+        join(primary.fetch_retried(), secondary.fetch_retried()).await;
+    let retries = primary_retries + secondary_retries;
+    (verify(price, check, tolerance_percent), retries)
+}
+
+/// Primary `price` if it is within `tolerance_percent` of the `check` price.
+fn verify(
+    price: Result<Price, QueryError>,
+    check: Result<Price, QueryError>,
+    tolerance_percent: f64,
+) -> Result<Price, QueryError> {
+    let (price, check) = (price?, check?);
+
+    let difference = (price.bitcoin.usd - check.bitcoin.usd).abs() / price.bitcoin.usd * 100.0;
+    if difference.is_nan() || difference > tolerance_percent {
+        return Err(QueryError::SourcesDisagree {
+            a: price.bitcoin.usd,
+            b: check.bitcoin.usd,
+        });
+    }
+    Ok(price)
+}
+
+/// Median of `values`, averaging the middle pair for an even count.
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        Some((values[mid - 1] + values[mid]) / 2.0)
+    } else {
+        Some(values[mid])
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    /// Price of `usd` updated at `timestamp`.
+    pub(crate) fn price(usd: f64, timestamp: u64) -> Price {
+        Price {
+            bitcoin: PriceInfo {
+                usd,
+                eur: None,
+                gbp: None,
+                usd_market_cap: None,
+                usd_24h_vol: None,
+                usd_24h_change: None,
+                last_updated_at: timestamp,
+            },
+            source: None,
+            change: None,
+            confidence: None,
+            raw: None,
+        }
+    }
+
+    /// Source returning predefined results in order, counting fetches.
+    pub(crate) struct StubSource {
+        results: Mutex<VecDeque<Result<Price, QueryError>>>,
+        fetches: AtomicUsize,
+    }
+
+    impl StubSource {
+        pub(crate) fn new(results: impl IntoIterator<Item = Result<Price, QueryError>>) -> Self {
+            Self {
+                results: Mutex::new(results.into_iter().collect()),
+                fetches: AtomicUsize::new(0),
+            }
+        }
+
+        pub(crate) fn fetches(&self) -> usize {
+            self.fetches.load(Ordering::Relaxed)
+        }
+    }
+
+    impl PriceSource for StubSource {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            let result = self
+                .results
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("no more stub results");
+            Box::pin(async move { result })
+        }
+    }
+
+    impl PriceSource for Arc<StubSource> {
+        fn name(&self) -> &'static str {
+            (**self).name()
+        }
+
+        fn fetch(&self) -> BoxFuture<'_, Result<Price, QueryError>> {
+            (**self).fetch()
+        }
+    }
+
+    #[tokio::test]
+    async fn composite_falls_back_on_error() {
+        let primary = Arc::new(StubSource::new([Err(QueryError::EmptyBody)]));
+        let fallback = Arc::new(StubSource::new([Ok(price(100.0, 1))]));
+        let source = CompositePriceSource::new(Box::new(primary.clone()))
+            .with_fallback(Box::new(fallback.clone()));
+
+        let price = source.fetch().await.unwrap();
+        assert_eq!(price.bitcoin.usd, 100.0);
+        assert_eq!((primary.fetches(), fallback.fetches()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn composite_stops_at_first_success() {
+        let primary = Arc::new(StubSource::new([Ok(price(100.0, 1))]));
+        let fallback = Arc::new(StubSource::new([Ok(price(200.0, 1))]));
+        let source = CompositePriceSource::new(Box::new(primary.clone()))
+            .with_fallback(Box::new(fallback.clone()));
+
+        let price = source.fetch().await.unwrap();
+        assert_eq!(price.bitcoin.usd, 100.0);
+        assert_eq!((primary.fetches(), fallback.fetches()), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn composite_returns_last_error() {
+        let source =
+            CompositePriceSource::new(Box::new(StubSource::new([Err(QueryError::EmptyBody)])))
+                .with_fallback(Box::new(StubSource::new([Err(QueryError::Unsupported)])));
+
+        let error = source.fetch().await.unwrap_err();
+        assert!(matches!(error, QueryError::Unsupported), "{error}");
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+
+use futures_util::future::BoxFuture;
+use serde::Serialize;
+use sqlx::types::chrono::Utc;
+
+use crate::error::QueryError;
+use crate::{Currency, Price};
+
+/// Storage of queried prices.
+pub trait PriceStore: Send + Sync {
+    /// Store price in all known currencies, replacing a price stored with the same timestamp.
+    fn push_price(&self, price: Price) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    /// Record that the price stored at `timestamp` was queried again unchanged at `seen_at`.
+    fn touch_price(&self, timestamp: u64, seen_at: u64) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    /// Most recent price known in `currency`, if any is stored.
+    fn latest_price(&self, currency: Currency) -> BoxFuture<'_, anyhow::Result<Option<Price>>>;
+}
+
+/// Bounded queue of prices waiting to be stored, e.g. while the store is unavailable.
+pub struct PendingWrites {
+    prices: VecDeque<Price>,
+    capacity: usize,
+}
+
+impl PendingWrites {
+    /// Empty queue holding at most `capacity` prices.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            prices: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Number of queued prices.
+    pub fn len(&self) -> usize {
+        self.prices.len()
+    }
+
+    /// Queue `price`, dropping the oldest queued price if the queue is full.
+    pub fn push(&mut self, price: Price) {
+        if self.prices.len() == self.capacity
+            && let Some(dropped) = self.prices.pop_front()
+        {
+            tracing::warn!("Price write queue is full, dropping price {dropped:?}.");
+        }
+        self.prices.push_back(price);
+    }
+
+    /// Store queued prices oldest first, stopping at the first failure.
+    ///
+    /// Prices that failed to be stored stay queued.
+    pub async fn flush(&mut self, store: &impl PriceStore) -> anyhow::Result<()> {
+        while let Some(price) = self.prices.front() {
+            store.push_price(price.clone()).await?;
+            self.prices.pop_front();
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of a price query.
+#[derive(Debug, Clone)]
+pub enum QueryOutcome {
+    /// Query succeeded at the unix timestamp.
+    Success(u64),
+
+    Failure(QueryFailure),
+}
+
+/// Record of a failed price query.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryFailure {
+    /// Unix timestamp of the failure.
+    pub timestamp: u64,
+
+    /// Failure category, see [`QueryError::kind`].
+    pub kind: String,
+
+    /// HTTP status of the failed response, if any.
+    pub status: Option<u16>,
+
+    pub message: String,
+}
+
+impl QueryFailure {
+    /// Record of `error` happening now.
+    pub fn new(error: &QueryError) -> Self {
+        Self {
+            timestamp: Utc::now().timestamp() as u64,
+            kind: error.kind().to_string(),
+            status: error.status().map(|status| status.as_u16()),
+            message: error.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::source::tests::price;
+
+    /// Store keeping prices in memory.
+    #[derive(Default)]
+    pub(crate) struct VecStore {
+        /// Stored prices, in order of storing.
+        pub prices: Mutex<Vec<Price>>,
+
+        /// Stored price timestamps and times they were seen again at.
+        pub touches: Mutex<Vec<(u64, u64)>>,
+    }
+
+    impl VecStore {
+        /// USD prices stored so far, in order of storing.
+        pub fn usd_prices(&self) -> Vec<f64> {
+            let prices = self.prices.lock().unwrap();
+            prices.iter().map(|price| price.bitcoin.usd).collect()
+        }
+    }
+
+    impl PriceStore for VecStore {
+        fn push_price(&self, price: Price) -> BoxFuture<'_, anyhow::Result<()>> {
+            let mut prices = self.prices.lock().unwrap();
+            let timestamp = price.bitcoin.last_updated_at;
+            prices.retain(|stored| stored.bitcoin.last_updated_at != timestamp);
+            prices.push(price);
+            Box::pin(async { Ok(()) })
+        }
+
+        fn touch_price(&self, timestamp: u64, seen_at: u64) -> BoxFuture<'_, anyhow::Result<()>> {
+            self.touches.lock().unwrap().push((timestamp, seen_at));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn latest_price(&self, currency: Currency) -> BoxFuture<'_, anyhow::Result<Option<Price>>> {
+            let prices = self.prices.lock().unwrap();
+            let latest = prices
+                .iter()
+                .filter(|price| price.bitcoin.price(currency).is_some())
+                .max_by_key(|price| price.bitcoin.last_updated_at)
+                .cloned();
+            Box::pin(async move { Ok(latest) })
+        }
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_oldest_price() {
+        let mut pending = PendingWrites::new(2);
+        for (usd, timestamp) in [(100.0, 1), (101.0, 2), (102.0, 3)] {
+            pending.push(price(usd, timestamp));
+        }
+        let store = VecStore::default();
+
+        pending.flush(&store).await.unwrap();
+
+        assert_eq!(store.usd_prices(), [101.0, 102.0]);
+        assert_eq!(pending.len(), 0);
+    }
+}
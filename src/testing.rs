@@ -0,0 +1,76 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::Router;
+use axum::serve::Listener;
+use reqwest::Url;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_native_tls::TlsStream;
+use tokio_native_tls::native_tls::{Identity, TlsAcceptor};
+
+use crate::http::HttpConfig;
+
+/// CoinGecko simple price response body with a USD price of 63214.57 updated at 1700000000.
+pub const PRICE_BODY: &str = r#"{"bitcoin":{"usd":63214.57,"last_updated_at":1700000000}}"#;
+
+/// Serve `router` on a random local port, returning its base URL.
+pub async fn serve(router: Router) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{addr}/").parse().unwrap()
+}
+
+/// HTTP settings of clients querying local mock servers without delays.
+pub fn http_config() -> HttpConfig {
+    HttpConfig {
+        timeout: Duration::from_secs(5),
+        min_interval: Duration::ZERO,
+        ..HttpConfig::default()
+    }
+}
+
+/// Serve `router` over TLS with a self-signed `localhost` certificate on a random local port,
+/// returning its base URL.
+pub async fn serve_tls(router: Router) -> Url {
+    let identity = Identity::from_pkcs8(
+        include_bytes!("testing/localhost.crt"),
+        include_bytes!("testing/localhost.key"),
+    )
+    .unwrap();
+    let listener = TlsListener {
+        tcp: TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        acceptor: TlsAcceptor::new(identity).unwrap().into(),
+    };
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("https://{addr}/").parse().unwrap()
+}
+
+/// TCP listener accepting connections which complete a TLS handshake.
+struct TlsListener {
+    tcp: TcpListener,
+    acceptor: tokio_native_tls::TlsAcceptor,
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let Ok((stream, addr)) = self.tcp.accept().await else {
+                continue;
+            };
+            // Clients rejecting the certificate abort the handshake.
+            if let Ok(stream) = self.acceptor.accept(stream).await {
+                return (stream, addr);
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.tcp.local_addr()
+    }
+}